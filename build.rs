@@ -0,0 +1,53 @@
+//! Hashes the game sources into `GAME_CONTENT_HASH`, so peers running different builds can refuse each other.
+
+use std::{fs, path::Path};
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/game");
+
+    let mut files = Vec::new();
+    collect_files(Path::new("src/game"), &mut files);
+    files.sort();
+
+    let mut hash = Fnv1a::default();
+    for file in files {
+        // paths and line endings are normalized so every platform gets the same hash
+        hash.write(file.replace('\\', "/").as_bytes());
+        let contents = fs::read(&file).expect("failed to read game source");
+        hash.write(
+            &contents
+                .into_iter()
+                .filter(|byte| *byte != b'\r')
+                .collect::<Vec<_>>(),
+        );
+    }
+    println!("cargo:rustc-env=GAME_CONTENT_HASH={:016x}", hash.0);
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).expect("failed to read game sources") {
+        let path = entry.expect("failed to read game sources").path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path.to_string_lossy().into_owned());
+        }
+    }
+}
+
+/// Same FNV-1a as the level digests.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
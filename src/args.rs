@@ -1,5 +1,7 @@
 use bevy::ecs::resource::Resource;
-use clap::Parser;
+use std::{fmt, str::FromStr};
+
+use clap::{Parser, ValueEnum};
 
 #[derive(Parser, Resource, Debug, Clone)]
 pub struct Args {
@@ -7,4 +9,107 @@ pub struct Args {
     pub synctest: bool,
     #[clap(short, long, default_value = "")]
     pub iroh: String,
+    #[clap(short, long, default_value = "classic")]
+    pub level: String,
+    /// Play on the local network only, without n0's discovery and relay servers.
+    #[clap(long)]
+    pub lan: bool,
+    /// Use a fresh identity instead of the stored one, so the room code changes.
+    #[clap(long)]
+    pub ephemeral: bool,
+    /// List the hosted room on the public room directory.
+    #[clap(long)]
+    pub public: bool,
+    /// Host name shown in the room directory.
+    #[clap(long, default_value = "")]
+    pub name: String,
+    /// Node id to meet other hosts and browsers of the room directory through, can be repeated.
+    #[clap(long = "directory-peer")]
+    pub directory_peers: Vec<String>,
+    /// Seconds between network stats written to the log during a match, 0 to turn it off.
+    #[clap(long, default_value_t = 10.0)]
+    pub network_stats_interval: f32,
+    /// Frames each input is delayed by to hide latency, or `auto` to pick it from the round trip time.
+    #[clap(long, default_value = "auto")]
+    pub input_delay: InputDelay,
+    /// Frames GGRS may predict ahead of the remote inputs before the match stalls.
+    #[clap(long, default_value_t = 8)]
+    pub max_prediction: usize,
+    /// Seconds to wait for the match to start before giving up, 0 to wait forever.
+    #[clap(long, default_value_t = 180)]
+    pub matchmaking_timeout: u64,
+    /// Relay servers iroh falls back to when peers cannot connect directly.
+    #[clap(long, value_enum, default_value_t)]
+    pub relay: RelayChoice,
+    /// URL of a self-hosted relay server for `--relay custom`, can be repeated or comma separated.
+    #[clap(long = "relay-url", value_delimiter = ',')]
+    pub relay_urls: Vec<String>,
+    /// STUN or TURN server for the WebRTC connections, can be repeated or comma separated.
+    /// Google's public STUN server is used if none is given.
+    #[clap(long = "ice-server", value_delimiter = ',')]
+    pub ice_servers: Vec<String>,
+    /// User name for the TURN servers among `--ice-server`.
+    #[clap(long)]
+    pub ice_username: Option<String>,
+    /// Password for the TURN servers among `--ice-server`.
+    #[clap(long)]
+    pub ice_credential: Option<String>,
+    /// How peers find each other before the WebRTC connections are up.
+    #[clap(long, value_enum, default_value_t)]
+    pub signalling: SignallingBackend,
+    /// Address of the matchbox signalling server, for `--signalling matchbox`.
+    #[clap(long, default_value = "ws://127.0.0.1:3536")]
+    pub signalling_server: String,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SignallingBackend {
+    /// Gossip over iroh, needs no server of our own.
+    #[default]
+    Iroh,
+    /// A standard matchbox websocket signalling server.
+    Matchbox,
+    /// In-process only, for several apps in one process.
+    Loopback,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RelayChoice {
+    /// n0's public relay servers.
+    #[default]
+    Default,
+    /// No relays, peers have to reach each other directly.
+    Disabled,
+    /// The servers given with `--relay-url`.
+    Custom,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputDelay {
+    /// Measured from the round trip time to the other players before the match starts.
+    #[default]
+    Auto,
+    Frames(usize),
+}
+
+impl FromStr for InputDelay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Auto);
+        }
+        s.parse()
+            .map(Self::Frames)
+            .map_err(|_| format!("expected `auto` or a number of frames, got `{s}`"))
+    }
+}
+
+impl fmt::Display for InputDelay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => f.write_str("auto"),
+            Self::Frames(frames) => write!(f, "{frames}"),
+        }
+    }
 }
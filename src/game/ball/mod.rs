@@ -1,18 +1,32 @@
-use bevy::{math::bounding::Aabb2d, prelude::*};
+use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_ggrs::prelude::*;
 
-use crate::game::field::Cell;
+use crate::game::field::{
+    Cell,
+    level::{CurrentLevel, Level},
+};
 
 use super::GameState;
-use super::components::Team;
+use super::components::{Position, Team};
 use super::field::{CellClicked, Wall};
-use super::item::spawn_item;
+use super::fixed::{Fixed, FixedAabb, FixedVec2};
+use super::item::{
+    effect::{self, ActiveEffect, EffectKind, SpeedFactor},
+    spawn_item,
+};
 use super::paddle::{Paddle, move_paddles};
+use super::round::RoundReset;
+use super::rules::MatchRules;
+use sticky::StuckBall;
+use sweep::{Contact, sweep_circle_aabb};
 
 mod respawn;
+mod sticky;
+mod sweep;
 
-pub const FIRST_BALL_SPEED: f32 = 300.0;
 pub const BALL_RADIUS: f32 = 10.0;
+/// Upper bound on contacts resolved per ball and tick, so wedged balls cannot loop forever.
+const MAX_BOUNCES: usize = 16;
 
 pub struct BallPlugin;
 
@@ -22,8 +36,8 @@ impl Plugin for BallPlugin {
             .add_systems(
                 GgrsSchedule,
                 (
-                    apply_velocity,
-                    check_collision,
+                    sticky::carry_stuck_balls,
+                    move_balls,
                     respawn::respawn_balls,
                     respawn::handle_respawning_balls,
                     respawn::despawn_stopped_balls,
@@ -33,138 +47,242 @@ impl Plugin for BallPlugin {
                     .before(spawn_item)
                     .run_if(in_state(GameState::InGame)),
             )
+            .add_systems(GgrsSchedule, reset_balls.in_set(RoundReset))
             .rollback_component_with_copy::<Ball>()
             .rollback_component_with_copy::<Velocity>()
-            .rollback_component_with_clone::<respawn::RespawningBall>();
+            .rollback_component_with_clone::<respawn::RespawningBall>()
+            .rollback_component_with_copy::<StuckBall>()
+            .checksum_component_with_hash::<Velocity>();
     }
 }
 
 #[derive(Component, Clone, Copy)]
+#[require(SpeedFactor)]
 pub struct Ball {
-    pub radius: f32,
+    pub radius: Fixed,
 }
 
-#[derive(Component, Deref, DerefMut, Clone, Copy, Debug)]
-pub struct Velocity(pub Vec2);
+impl Default for Ball {
+    fn default() -> Self {
+        Self {
+            radius: Fixed::from_f32(BALL_RADIUS),
+        }
+    }
+}
+
+#[derive(Component, Deref, DerefMut, Clone, Copy, Debug, Hash)]
+pub struct Velocity(pub FixedVec2);
 
 fn setup_ball(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    current_level: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
+    rules: Res<MatchRules>,
+) {
+    let Some(level) = levels.get(&current_level.0) else {
+        error!("Current level is not loaded, cannot set up balls.");
+        return;
+    };
+
+    spawn_lane_balls(&mut commands, &mut meshes, &mut materials, level, &rules);
+}
+
+/// Removes every ball, including respawning and stuck ones, and serves fresh ones for the next round.
+fn reset_balls(
+    mut commands: Commands,
+    q_ball: Query<Entity, With<Ball>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    current_level: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
+    rules: Res<MatchRules>,
 ) {
-    let initial_velocity = Vec2::new(1.0, 1.0).normalize() * FIRST_BALL_SPEED;
-    commands
-        .spawn((
-            Ball {
-                radius: BALL_RADIUS,
-            },
-            Team(0),
-            Mesh2d(meshes.add(Mesh::from(Circle::new(BALL_RADIUS)))),
-            MeshMaterial2d(materials.add(Color::srgb(0., 0., 0.))),
-            Transform::from_xyz(0., -300., 10.),
-            Velocity(initial_velocity),
-        ))
-        .add_rollback();
-    commands
-        .spawn((
-            Ball {
-                radius: BALL_RADIUS,
-            },
-            Team(1),
-            Mesh2d(meshes.add(Mesh::from(Circle::new(BALL_RADIUS)))),
-            MeshMaterial2d(materials.add(Color::srgb(0., 0., 0.))),
-            Transform::from_xyz(0., 300., 10.),
-            Velocity(initial_velocity),
-        ))
-        .add_rollback();
+    for entity in &q_ball {
+        commands.entity(entity).despawn();
+    }
+    if let Some(level) = levels.get(&current_level.0) {
+        spawn_lane_balls(&mut commands, &mut meshes, &mut materials, level, &rules);
+    }
 }
 
-fn apply_velocity(time: Res<Time>, q_ball: Query<(&Velocity, &mut Transform), With<Ball>>) {
-    for (velocity, mut transform) in q_ball {
-        transform.translation += velocity.extend(0.) * time.delta_secs();
+fn spawn_lane_balls(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    level: &Level,
+    rules: &MatchRules,
+) {
+    for lane in &level.paddle_lanes {
+        // diagonally into the field, to the right as seen from the lane
+        let initial_velocity = (lane.side.normal() + lane.side.tangent()).normalize_or_zero()
+            * Fixed::from_f32(rules.first_ball_speed);
+        commands
+            .spawn((
+                Ball::default(),
+                Team(lane.team),
+                Mesh2d(meshes.add(Mesh::from(Circle::new(BALL_RADIUS)))),
+                MeshMaterial2d(materials.add(Color::srgb(0., 0., 0.))),
+                Position(FixedVec2::from_vec2(Vec2::from(lane.ball_spawn))),
+                Transform::from_translation(Vec2::from(lane.ball_spawn).extend(10.)),
+                Velocity(initial_velocity),
+            ))
+            .add_rollback();
     }
 }
 
-fn check_collision(
+/// Everything a ball can bounce off.
+#[derive(SystemParam)]
+struct Obstacles<'w, 's> {
+    cells: Query<'w, 's, (Entity, &'static Cell, &'static Team, &'static Position), Without<Ball>>,
+    walls: Query<'w, 's, (&'static Wall, &'static Position, Option<&'static Team>), Without<Ball>>,
+    paddles: Query<'w, 's, (&'static Paddle, &'static Team, &'static Position), Without<Ball>>,
+}
+
+/// Sweeps every ball along its velocity for this tick, bouncing off everything it touches on the way.
+fn move_balls(
     mut commands: Commands,
-    q_ball: Query<(Entity, &Ball, &Team, &mut Transform, &mut Velocity)>,
-    q_cell: Query<(Entity, &Cell, &Team, &Transform), Without<Ball>>,
-    q_wall: Query<(&Wall, &Transform, Option<&Team>), Without<Ball>>,
-    q_paddle: Query<(&Paddle, &Team, &Transform), Without<Ball>>,
+    time: Res<Time>,
+    q_ball: Query<(Entity, &Ball, &Team, &mut Position, &mut Velocity), Without<StuckBall>>,
+    obstacles: Obstacles,
+    q_effects: Query<(&ActiveEffect, &Team)>,
+    rules: Res<MatchRules>,
     mut events: MessageWriter<CellClicked>,
 ) {
-    'ball: for (ball_entity, ball, ball_team, mut ball_transform, mut velocity) in q_ball {
-        let ball_pos = ball_transform.translation.truncate();
+    let delta = Fixed::from_duration(time.delta());
+    // cell teams only change in `toggle_cell`, so remember what was already hit this tick
+    let mut captured = Vec::new();
 
-        // Check for wall collisions
-        for (wall, wall_transform, wall_team) in &q_wall {
-            let closest_point = Aabb2d::new(wall_transform.translation.truncate(), wall.half_size)
-                .closest_point(ball_pos);
+    'ball: for (ball_entity, ball, ball_team, mut position, mut velocity) in q_ball {
+        let mut remaining = Fixed::ONE;
+        let sticky = effect::is_active(&q_effects, ball_team, EffectKind::StickyPaddle);
+        let piercing = effect::is_active(&q_effects, ball_team, EffectKind::PiercingBall);
+        let shielded = effect::is_active(&q_effects, ball_team, EffectKind::Shield);
 
-            if closest_point == ball_pos {
-                commands.entity(ball_entity).despawn();
-                continue 'ball;
-            }
+        for _ in 0..MAX_BOUNCES {
+            let displacement = velocity.0 * delta * remaining;
+            let mut first = None;
 
-            let diff = ball_pos - closest_point;
-            if diff.length_squared() < ball.radius * ball.radius {
-                if wall_team.map(|team| team == ball_team).unwrap_or(false) {
-                    commands.entity(ball_entity).despawn();
-                    continue 'ball;
-                }
-                let normal = diff.normalize();
-                velocity.0 = velocity.reflect(normal);
-                ball_transform.translation += (normal * (ball.radius - diff.length())).extend(0.);
+            for (wall, wall_position, wall_team) in &obstacles.walls {
+                let aabb = FixedAabb::new(wall_position.0, wall.half_size);
+                keep_earliest(
+                    &mut first,
+                    sweep_circle_aabb(position.0, ball.radius, displacement, &aabb),
+                    Obstacle::Wall {
+                        own: wall_team == Some(ball_team),
+                    },
+                );
             }
-        }
-
-        // Check for paddle collisions
-        for (paddle, paddle_team, paddle_transform) in &q_paddle {
-            let paddle_aabb =
-                Aabb2d::new(paddle_transform.translation.truncate(), paddle.half_size);
-            let closest_point = paddle_aabb.closest_point(ball_pos);
-
-            let diff = ball_pos - closest_point;
-            if diff.length_squared() < ball.radius * ball.radius {
-                let normal = diff.normalize();
-
-                // Calculate reflection angle based on hit position
-                let hit_position =
-                    (ball_pos.x - paddle_transform.translation.x) / paddle.half_size.x;
-                let angle = hit_position * std::f32::consts::PI / 3.0; // Max 60 degrees
-
-                let speed = velocity.length();
-                let dir = 1. - 2. * paddle_team.0 as f32; // Team 0: up, Team 1: down
-                velocity.0 = Vec2::new(angle.sin(), dir * angle.cos()) * speed;
-                ball_transform.translation += (normal * (ball.radius - diff.length())).extend(0.);
-                continue 'ball;
+            for (paddle, paddle_team, paddle_position) in &obstacles.paddles {
+                let aabb = FixedAabb::new(paddle_position.0, paddle.half_size());
+                keep_earliest(
+                    &mut first,
+                    sweep_circle_aabb(position.0, ball.radius, displacement, &aabb),
+                    Obstacle::Paddle {
+                        center: paddle_position.0,
+                        paddle: *paddle,
+                        team: *paddle_team,
+                    },
+                );
             }
-        }
-
-        // Check for cell collisions
-        for (cell_entity, cell, cell_team, cell_transform) in &q_cell {
-            if cell_team == ball_team {
-                continue;
+            for (cell_entity, cell, cell_team, cell_position) in &obstacles.cells {
+                if rules.allied(*cell_team, *ball_team) || captured.contains(&cell_entity) {
+                    continue;
+                }
+                let aabb = FixedAabb::new(cell_position.0, cell.half_size);
+                keep_earliest(
+                    &mut first,
+                    sweep_circle_aabb(position.0, ball.radius, displacement, &aabb),
+                    Obstacle::Cell(cell_entity),
+                );
             }
-            let closest_point = Aabb2d::new(cell_transform.translation.truncate(), cell.half_size)
-                .closest_point(ball_pos);
 
-            if closest_point == ball_pos {
-                commands.entity(ball_entity).despawn();
+            let Some((contact, obstacle)) = first else {
+                position.0 += displacement;
                 continue 'ball;
-            }
+            };
+            position.0 += displacement * contact.time;
+            remaining *= Fixed::ONE - contact.time;
+            let embedded = contact.normal == FixedVec2::ZERO;
 
-            let diff = ball_pos - closest_point;
-            if diff.length_squared() < ball.radius * ball.radius {
-                let normal = diff.normalize();
-                velocity.0 = velocity.reflect(normal);
-                ball_transform.translation += (normal * (ball.radius - diff.length())).extend(0.);
-                events.write(CellClicked {
-                    cell: cell_entity,
-                    team: *ball_team,
-                });
-                continue 'ball;
+            match obstacle {
+                Obstacle::Wall { own: true } if shielded && !embedded => {
+                    velocity.0 = velocity.reflect(contact.normal);
+                }
+                Obstacle::Wall { own: true } => {
+                    commands.entity(ball_entity).despawn();
+                    continue 'ball;
+                }
+                Obstacle::Cell(cell_entity) if piercing => {
+                    captured.push(cell_entity);
+                    events.write(CellClicked {
+                        cell: cell_entity,
+                        team: *ball_team,
+                    });
+                }
+                Obstacle::Wall { own: false } | Obstacle::Cell(_) if embedded => {
+                    commands.entity(ball_entity).despawn();
+                    continue 'ball;
+                }
+                Obstacle::Wall { own: false } => {
+                    velocity.0 = velocity.reflect(contact.normal);
+                }
+                Obstacle::Paddle {
+                    center,
+                    paddle,
+                    team,
+                } if sticky && team == *ball_team => {
+                    let offset = paddle.offset(center, position.0);
+                    position.0 = paddle.surface(center, offset, ball.radius);
+                    velocity.0 = FixedVec2::ZERO;
+                    commands.entity(ball_entity).insert(StuckBall { offset });
+                    continue 'ball;
+                }
+                Obstacle::Paddle { center, paddle, .. } => {
+                    // Calculate reflection angle based on hit position
+                    let offset = paddle.offset(center, position.0);
+                    velocity.0 = paddle.launch_velocity(offset, velocity.length());
+                    if embedded {
+                        // the paddle moved onto the ball, put it back in front of the paddle
+                        position.0 = paddle.surface(center, offset, ball.radius);
+                    }
+                }
+                Obstacle::Cell(cell_entity) => {
+                    velocity.0 = velocity.reflect(contact.normal);
+                    captured.push(cell_entity);
+                    events.write(CellClicked {
+                        cell: cell_entity,
+                        team: *ball_team,
+                    });
+                }
             }
         }
     }
 }
+
+#[derive(Clone, Copy)]
+enum Obstacle {
+    Wall {
+        own: bool,
+    },
+    Paddle {
+        center: FixedVec2,
+        paddle: Paddle,
+        team: Team,
+    },
+    Cell(Entity),
+}
+
+fn keep_earliest(
+    first: &mut Option<(Contact, Obstacle)>,
+    contact: Option<Contact>,
+    obstacle: Obstacle,
+) {
+    let Some(contact) = contact else {
+        return;
+    };
+    if first.is_none_or(|(earliest, _)| contact.time < earliest.time) {
+        *first = Some((contact, obstacle));
+    }
+}
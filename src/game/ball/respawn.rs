@@ -3,9 +3,15 @@ use std::time::Duration;
 use bevy::prelude::*;
 use bevy_ggrs::prelude::*;
 
-use crate::game::{components::Team, paddle::Paddle};
+use crate::game::{
+    components::{Position, Team},
+    fixed::{Fixed, FixedVec2},
+    item::effect::SpeedFactor,
+    paddle::Paddle,
+    rules::MatchRules,
+};
 
-use super::{BALL_RADIUS, Ball, FIRST_BALL_SPEED, Velocity};
+use super::{BALL_RADIUS, Ball, Velocity, sticky::StuckBall};
 
 #[derive(Component, Clone)]
 pub struct RespawningBall(Timer);
@@ -15,28 +21,22 @@ pub fn respawn_balls(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     q_ball: Query<&Team, With<Ball>>,
+    q_paddle: Query<&Team, With<Paddle>>,
 ) {
-    let counts = q_ball.iter().fold([0; 2], |mut acc, team| {
-        acc[team.0] += 1;
-        acc
-    });
-
-    for (team, _) in counts
-        .into_iter()
-        .enumerate()
-        .filter(|(_, count)| *count == 0)
-    {
+    for team in q_paddle {
+        if q_ball.iter().any(|ball_team| ball_team == team) {
+            continue;
+        }
         commands
             .spawn((
                 RespawningBall(Timer::new(Duration::from_secs(3), TimerMode::Once)),
-                Ball {
-                    radius: BALL_RADIUS,
-                },
-                Team(team),
+                Ball::default(),
+                *team,
                 Mesh2d(meshes.add(Mesh::from(Circle::new(BALL_RADIUS)))),
                 MeshMaterial2d(materials.add(Color::srgb(0., 0., 0.))),
+                Position(FixedVec2::new(Fixed::ZERO, Fixed::from_int(10000))),
                 Transform::from_xyz(0., 10000., 10.),
-                Velocity(Vec2::ZERO),
+                Velocity(FixedVec2::ZERO),
             ))
             .add_rollback();
     }
@@ -48,30 +48,30 @@ pub fn handle_respawning_balls(
     q_ball: Query<(
         Entity,
         &mut RespawningBall,
-        &mut Transform,
+        &mut Position,
         &mut Velocity,
+        &mut SpeedFactor,
         &Team,
     )>,
-    q_paddle: Query<(&Team, &Transform), (With<Paddle>, Without<RespawningBall>)>,
+    q_paddle: Query<(&Paddle, &Team, &Position), Without<RespawningBall>>,
     time: Res<Time>,
+    rules: Res<MatchRules>,
 ) {
-    for (entity, mut timer, mut transform, mut velocity, team) in q_ball {
+    for (entity, mut timer, mut position, mut velocity, mut factor, team) in q_ball {
         timer.0.tick(time.delta());
-        let Some(paddle_transform) = q_paddle
+        let Some((paddle, _, paddle_position)) = q_paddle
             .iter()
-            .find(|(paddle_team, _)| paddle_team.0 == team.0)
-            .map(|(_, transform)| transform)
+            .find(|(_, paddle_team, _)| *paddle_team == team)
         else {
             continue;
         };
 
-        let relative_y = 1. - 2. * team.0 as f32;
-
-        transform.translation.x = paddle_transform.translation.x;
-        transform.translation.y = paddle_transform.translation.y + relative_y * 5. * BALL_RADIUS;
+        position.0 = paddle_position.0 + paddle.side.normal() * Fixed::from_f32(5. * BALL_RADIUS);
 
         if timer.0.is_finished() {
-            velocity.0 = Vec2::new(0., relative_y).normalize() * FIRST_BALL_SPEED;
+            velocity.0 = paddle.side.normal() * Fixed::from_f32(rules.first_ball_speed);
+            // the running effects apply on top of the launch speed
+            *factor = SpeedFactor::default();
             commands.entity(entity).remove::<RespawningBall>();
         }
     }
@@ -80,10 +80,10 @@ pub fn handle_respawning_balls(
 #[allow(clippy::type_complexity)]
 pub fn despawn_stopped_balls(
     mut commands: Commands,
-    q_ball: Query<(Entity, &Velocity), (With<Ball>, Without<RespawningBall>)>,
+    q_ball: Query<(Entity, &Velocity), (With<Ball>, Without<RespawningBall>, Without<StuckBall>)>,
 ) {
     for (entity, velocity) in q_ball {
-        if velocity.0.length_squared() < 0.01 {
+        if velocity.length() < Fixed::from_ratio(1, 10) {
             commands.entity(entity).despawn();
         }
     }
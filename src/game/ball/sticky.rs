@@ -0,0 +1,60 @@
+use bevy::prelude::*;
+use bevy_ggrs::PlayerInputs;
+
+use crate::game::{
+    Config,
+    components::{Position, Team},
+    fixed::Fixed,
+    item::effect::{self, ActiveEffect, EffectKind, SpeedFactor},
+    paddle::{INPUT_RELEASE, Paddle},
+    rules::MatchRules,
+};
+
+use super::{Ball, Velocity};
+
+/// Ball held by a sticky paddle, `offset` along the paddle from its center.
+#[derive(Component, Clone, Copy)]
+pub struct StuckBall {
+    pub offset: Fixed,
+}
+
+/// Keeps stuck balls on their paddle and launches them on input or once the effect runs out.
+pub fn carry_stuck_balls(
+    mut commands: Commands,
+    q_ball: Query<(
+        Entity,
+        &Ball,
+        &Team,
+        &StuckBall,
+        &mut Position,
+        &mut Velocity,
+        &mut SpeedFactor,
+    )>,
+    q_paddle: Query<(&Paddle, &Team, &Position), Without<Ball>>,
+    q_effects: Query<(&ActiveEffect, &Team)>,
+    inputs: Res<PlayerInputs<Config>>,
+    rules: Res<MatchRules>,
+) {
+    for (entity, ball, team, stuck, mut position, mut velocity, mut factor) in q_ball {
+        let Some((paddle, _, paddle_position)) = q_paddle
+            .iter()
+            .find(|(_, paddle_team, _)| *paddle_team == team)
+        else {
+            continue;
+        };
+
+        let offset = stuck.offset.clamp(-paddle.half_width, paddle.half_width);
+        position.0 = paddle.surface(paddle_position.0, offset, ball.radius);
+
+        let (input, _) = inputs[team.0];
+        if input & INPUT_RELEASE != 0
+            || !effect::is_active(&q_effects, team, EffectKind::StickyPaddle)
+        {
+            // same angle as a bounce at this spot of the paddle
+            velocity.0 = paddle.launch_velocity(offset, Fixed::from_f32(rules.first_ball_speed));
+            // the running effects apply on top of the launch speed
+            *factor = SpeedFactor::default();
+            commands.entity(entity).remove::<StuckBall>();
+        }
+    }
+}
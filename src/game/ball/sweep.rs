@@ -0,0 +1,211 @@
+use crate::game::fixed::{Fixed, FixedAabb, FixedVec2};
+
+/// First contact of a moving circle with an obstacle.
+#[derive(Clone, Copy, Debug)]
+pub struct Contact {
+    /// Fraction of the displacement travelled before the contact, in `0..=1`.
+    pub time: Fixed,
+    /// Surface normal at the contact, or zero if the circle center is already inside the obstacle.
+    pub normal: FixedVec2,
+}
+
+/// Sweeps a circle along `displacement` and returns its first contact with `aabb`.
+pub fn sweep_circle_aabb(
+    center: FixedVec2,
+    radius: Fixed,
+    displacement: FixedVec2,
+    aabb: &FixedAabb,
+) -> Option<Contact> {
+    let closest_point = aabb.closest_point(center);
+    if closest_point == center {
+        return Some(Contact {
+            time: Fixed::ZERO,
+            normal: FixedVec2::ZERO,
+        });
+    }
+
+    // Already touching: only report it if the circle keeps moving in
+    let diff = center - closest_point;
+    if diff.length() < radius {
+        let normal = diff.normalize_or_zero();
+        return (displacement.dot(normal) < Fixed::ZERO).then_some(Contact {
+            time: Fixed::ZERO,
+            normal,
+        });
+    }
+
+    if displacement == FixedVec2::ZERO {
+        return None;
+    }
+
+    // Cast the center against the box grown by the radius
+    let grown = FixedAabb {
+        min: aabb.min - FixedVec2::splat(radius),
+        max: aabb.max + FixedVec2::splat(radius),
+    };
+    let (enter_x, exit_x, normal_x) = slab(center.x, displacement.x, grown.min.x, grown.max.x)?;
+    let (enter_y, exit_y, normal_y) = slab(center.y, displacement.y, grown.min.y, grown.max.y)?;
+    let (time, normal) = if enter_y < enter_x {
+        (enter_x, FixedVec2::new(normal_x, Fixed::ZERO))
+    } else {
+        (enter_y, FixedVec2::new(Fixed::ZERO, normal_y))
+    };
+    if exit_x.min(exit_y) < time || Fixed::ONE < time {
+        return None;
+    }
+
+    // The grown box has square corners, but the real shape is rounded there
+    let point = center + displacement * time.max(Fixed::ZERO);
+    let outside_x = point.x < aabb.min.x || aabb.max.x < point.x;
+    let outside_y = point.y < aabb.min.y || aabb.max.y < point.y;
+    if outside_x && outside_y {
+        return sweep_circle_point(center, radius, displacement, aabb.closest_point(point));
+    }
+
+    (Fixed::ZERO <= time).then_some(Contact { time, normal })
+}
+
+/// Entry and exit times of a ray against `min..=max` on one axis, with the normal of the entry side.
+fn slab(origin: Fixed, delta: Fixed, min: Fixed, max: Fixed) -> Option<(Fixed, Fixed, Fixed)> {
+    if delta == Fixed::ZERO {
+        return (min <= origin && origin <= max).then_some((Fixed::MIN, Fixed::MAX, Fixed::ZERO));
+    }
+    let (near, far) = if Fixed::ZERO < delta {
+        (min, max)
+    } else {
+        (max, min)
+    };
+    Some((
+        (near - origin) / delta,
+        (far - origin) / delta,
+        -delta.signum(),
+    ))
+}
+
+/// Sweeps a circle along `displacement` against a single point, such as a box corner.
+fn sweep_circle_point(
+    center: FixedVec2,
+    radius: Fixed,
+    displacement: FixedVec2,
+    point: FixedVec2,
+) -> Option<Contact> {
+    // Solve in distance units so the squares stay small even for very fast balls
+    let length = displacement.length();
+    let direction = displacement / length;
+    let offset = center - point;
+    let b = offset.dot(direction);
+    let c = offset.dot(offset) - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < Fixed::ZERO {
+        return None;
+    }
+
+    let distance = -b - discriminant.sqrt();
+    if distance < Fixed::ZERO || length < distance {
+        return None;
+    }
+    let time = distance / length;
+    Some(Contact {
+        time,
+        normal: (center + displacement * time - point).normalize_or_zero(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vec(x: i64, y: i64) -> FixedVec2 {
+        FixedVec2::new(Fixed::from_int(x), Fixed::from_int(y))
+    }
+
+    fn square(x: i64, y: i64, half_size: i64) -> FixedAabb {
+        FixedAabb::new(vec(x, y), vec(half_size, half_size))
+    }
+
+    fn assert_close(actual: Fixed, expected: f32, tolerance: f32) {
+        assert!(
+            (actual.to_f32() - expected).abs() <= tolerance,
+            "{} is not {expected}",
+            actual.to_f32()
+        );
+    }
+
+    #[test]
+    fn hits_face() {
+        let contact = sweep_circle_aabb(
+            vec(0, 0),
+            Fixed::from_int(10),
+            vec(100, 0),
+            &square(50, 0, 5),
+        )
+        .expect("ball should hit the box");
+        assert_close(contact.time, 0.35, 1e-3);
+        assert_eq!(contact.normal, vec(-1, 0));
+    }
+
+    #[test]
+    fn hits_rounded_corner() {
+        let contact = sweep_circle_aabb(
+            vec(0, 0),
+            Fixed::from_int(10),
+            vec(100, 100),
+            &square(50, 50, 5),
+        )
+        .expect("ball should hit the corner");
+        // the center stops 10 away from the corner (45, 45) on the diagonal
+        assert_close(
+            contact.time,
+            (45.0 * 2f32.sqrt() - 10.0) / (100.0 * 2f32.sqrt()),
+            1e-3,
+        );
+        assert_close(contact.normal.x, -0.5f32.sqrt(), 1e-2);
+        assert_close(contact.normal.y, -0.5f32.sqrt(), 1e-2);
+    }
+
+    #[test]
+    fn misses_past_corner() {
+        // inside the grown box, but more than a radius away from the real corner
+        let contact = sweep_circle_aabb(
+            vec(0, 30),
+            Fixed::from_int(10),
+            vec(100, 100),
+            &square(50, 50, 5),
+        );
+        assert!(contact.is_none(), "{contact:?}");
+    }
+
+    #[test]
+    fn starting_touching() {
+        let aabb = square(50, 0, 5);
+        let moving_in = sweep_circle_aabb(vec(37, 0), Fixed::from_int(10), vec(10, 0), &aabb)
+            .expect("ball moving in should hit");
+        assert_eq!(moving_in.time, Fixed::ZERO);
+        assert_eq!(moving_in.normal, vec(-1, 0));
+
+        let moving_out = sweep_circle_aabb(vec(37, 0), Fixed::from_int(10), vec(-10, 0), &aabb);
+        assert!(moving_out.is_none(), "{moving_out:?}");
+
+        let inside = sweep_circle_aabb(vec(50, 0), Fixed::from_int(10), vec(10, 0), &aabb)
+            .expect("ball inside should hit");
+        assert_eq!(inside.time, Fixed::ZERO);
+        assert_eq!(inside.normal, FixedVec2::ZERO);
+    }
+
+    #[test]
+    fn does_not_tunnel_at_high_speed() {
+        // a cell-thin wall far away, crossed within a single step
+        let wall = FixedAabb::new(
+            vec(500, 0),
+            FixedVec2::new(Fixed::from_ratio(5, 2), Fixed::from_int(100)),
+        );
+        let contact = sweep_circle_aabb(vec(0, 0), Fixed::from_int(10), vec(100_000, 0), &wall)
+            .expect("fast ball should hit the wall");
+        assert_close(contact.time * Fixed::from_int(100_000), 487.5, 1.0);
+        assert_eq!(contact.normal, vec(-1, 0));
+
+        let contact = sweep_circle_aabb(vec(0, 0), Fixed::from_int(10), vec(100_000, 1_000), &wall)
+            .expect("fast diagonal ball should hit the wall");
+        assert_eq!(contact.normal, vec(-1, 0));
+    }
+}
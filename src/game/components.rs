@@ -1,19 +1,66 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
 use bevy::prelude::*;
+use serde::Deserialize;
+
+use super::fixed::{Fixed, FixedVec2};
 
+/// Owner of cells, balls and paddles. Players are numbered by their GGRS handle.
 #[derive(Component, Deref, DerefMut, PartialEq, Clone, Copy)]
 pub struct Team(pub usize);
 
 impl Team {
-    pub const ITEM: Self = Self(2);
+    pub const ITEM: Self = Self(usize::MAX);
 
     pub fn hue(&self) -> f32 {
         match self.0 {
             0 => 0.,
             1 => 180.,
+            2 => 120.,
+            3 => 270.,
             _ => 60.,
         }
     }
 }
 
-#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Deref, DerefMut)]
-pub struct Count(pub usize);
+/// Edge of the field a paddle lane guards.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Bottom,
+    Top,
+    Left,
+    Right,
+}
+
+impl Side {
+    /// Direction from the lane into the field.
+    pub fn normal(self) -> FixedVec2 {
+        match self {
+            Side::Bottom => FixedVec2::new(Fixed::ZERO, Fixed::ONE),
+            Side::Top => FixedVec2::new(Fixed::ZERO, -Fixed::ONE),
+            Side::Left => FixedVec2::new(Fixed::ONE, Fixed::ZERO),
+            Side::Right => FixedVec2::new(-Fixed::ONE, Fixed::ZERO),
+        }
+    }
+
+    /// Direction along the lane that is "right" for the player guarding it.
+    pub fn tangent(self) -> FixedVec2 {
+        let normal = self.normal();
+        FixedVec2::new(normal.y, -normal.x)
+    }
+
+    /// Camera rotation that puts this side at the bottom of the screen.
+    pub fn camera_angle(self) -> f32 {
+        match self {
+            Side::Bottom => 0.,
+            Side::Top => PI,
+            Side::Left => -FRAC_PI_2,
+            Side::Right => FRAC_PI_2,
+        }
+    }
+}
+
+/// Position in the simulation. `Transform` is only updated from it for rendering.
+#[derive(Component, Deref, DerefMut, Clone, Copy, Debug, Hash)]
+pub struct Position(pub FixedVec2);
@@ -0,0 +1,149 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, embedded_asset, io::Reader},
+    platform::collections::HashMap,
+    prelude::*,
+};
+use serde::Deserialize;
+
+use crate::game::components::{Side, Team};
+
+use super::CELL_SIZE;
+
+/// Names of the bundled levels, in the order they are offered in the lobby.
+pub const LEVELS: &[&str] = &["classic", "fortress", "pillars", "arena"];
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        embedded_asset!(app, "levels/classic.level.json");
+        embedded_asset!(app, "levels/fortress.level.json");
+        embedded_asset!(app, "levels/pillars.level.json");
+        embedded_asset!(app, "levels/arena.level.json");
+
+        app.init_asset::<Level>()
+            .init_asset_loader::<LevelLoader>()
+            .add_systems(Startup, load_levels);
+    }
+}
+
+/// Field layout loaded from a `*.level.json` file.
+#[derive(Asset, TypePath, Deserialize, Debug)]
+pub struct Level {
+    /// Rows of the cell grid from top to bottom.
+    /// Each character is the initial owner of the cell (a player number) or `.` for no cell.
+    pub cells: Vec<String>,
+    pub walls: Vec<WallLayout>,
+    /// One lane per player, so the lane count is the number of players.
+    pub paddle_lanes: Vec<PaddleLane>,
+    /// Hash of the file contents with line endings normalised, used to check that both peers loaded the same layout.
+    #[serde(skip)]
+    pub digest: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct WallLayout {
+    pub center: [f32; 2],
+    pub size: [f32; 2],
+    /// A ball touching the wall of its own team is lost.
+    #[serde(default)]
+    pub team: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct PaddleLane {
+    pub team: usize,
+    pub side: Side,
+    pub position: [f32; 2],
+    pub ball_spawn: [f32; 2],
+}
+
+impl Level {
+    /// Returns the center and initial owner of every cell, with the grid centered on the origin.
+    pub fn cells(&self) -> impl Iterator<Item = (Vec2, Team)> + '_ {
+        let height = self.cells.len() as f32;
+        self.cells.iter().enumerate().flat_map(move |(row, line)| {
+            let width = line.chars().count() as f32;
+            line.chars().enumerate().filter_map(move |(column, c)| {
+                let team = Team(c.to_digit(10)? as usize);
+                let position = Vec2::new(
+                    column as f32 - width / 2. + 0.5,
+                    height / 2. - row as f32 - 0.5,
+                ) * CELL_SIZE;
+                Some((position, team))
+            })
+        })
+    }
+
+    pub fn lane(&self, team: Team) -> Option<&PaddleLane> {
+        self.paddle_lanes.iter().find(|lane| lane.team == team.0)
+    }
+
+    pub fn num_players(&self) -> usize {
+        self.paddle_lanes.len()
+    }
+}
+
+/// Handles of all bundled levels, keyed by name.
+#[derive(Resource, Default)]
+pub struct LevelHandles(pub HashMap<String, Handle<Level>>);
+
+impl LevelHandles {
+    pub fn get<'a>(&self, name: &str, assets: &'a Assets<Level>) -> Option<&'a Level> {
+        assets.get(self.0.get(name)?)
+    }
+
+    pub fn all_loaded(&self, assets: &Assets<Level>) -> bool {
+        self.0.values().all(|handle| assets.contains(handle))
+    }
+}
+
+/// The level played in the current match.
+#[derive(Resource, Clone)]
+pub struct CurrentLevel(pub Handle<Level>);
+
+fn load_levels(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let handles = LEVELS
+        .iter()
+        .map(|name| {
+            let path = format!("embedded://online_breakout/game/field/levels/{name}.level.json");
+            (name.to_string(), asset_server.load(path))
+        })
+        .collect();
+    commands.insert_resource(LevelHandles(handles));
+}
+
+#[derive(Default)]
+struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    type Asset = Level;
+    type Settings = ();
+    type Error = BevyError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Level, BevyError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let mut level: Level = serde_json::from_slice(&bytes)?;
+        // a checkout with CRLF line endings has the same layout as one with LF
+        bytes.retain(|byte| *byte != b'\r');
+        level.digest = fnv1a(&bytes);
+        Ok(level)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.json"]
+    }
+}
+
+/// 64-bit FNV-1a, which gives the same result on every platform unlike `DefaultHasher`.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
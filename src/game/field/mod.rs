@@ -1,165 +1,184 @@
 use bevy::prelude::*;
 use bevy_ggrs::{LocalPlayers, prelude::*};
 
+use level::{CurrentLevel, Level};
+
 use super::{
     GameState,
-    components::{Count, Team},
+    components::{Position, Team},
+    fixed::{Fixed, FixedVec2},
+    round::RoundReset,
 };
 
-pub const FIELD_WIDTH: i32 = 10;
-pub const FIELD_HEIGHT: i32 = 10;
+pub mod level;
+
 pub const CELL_SIZE: f32 = 50.;
 pub const CELL_THICKNESS: f32 = 5.;
+pub const WALL_COLOR: Color = Color::srgb(0.3, 0.3, 0.3);
+/// Every this many captures, the captured cell becomes an item cell instead.
+const ITEM_DROP_INTERVAL: usize = 10;
 
 pub struct FieldPlugin;
 
 impl Plugin for FieldPlugin {
     fn build(&self, app: &mut App) {
-        app.add_message::<CellClicked>()
+        app.add_plugins(level::LevelPlugin)
+            .add_message::<CellClicked>()
             .add_systems(OnEnter(GameState::InGame), setup_field)
             .add_systems(
                 GgrsSchedule,
                 toggle_cell.run_if(in_state(GameState::InGame)),
             )
+            .add_systems(GgrsSchedule, reset_field.in_set(RoundReset))
             .add_systems(
                 Update,
                 (rotate, update_cell_color).run_if(in_state(GameState::InGame)),
-            );
+            )
+            .rollback_resource_with_clone::<ItemDropCounter>()
+            .rollback_resource_with_clone::<CaptureCount>()
+            .checksum_resource_with_hash::<ItemDropCounter>()
+            .checksum_resource_with_hash::<CaptureCount>();
     }
 }
 
 #[derive(Clone, Copy, Component)]
 pub struct Wall {
-    pub half_size: Vec2,
+    pub half_size: FixedVec2,
 }
 
 #[derive(Component)]
 pub struct Cell {
-    pub half_size: Vec2,
+    pub half_size: FixedVec2,
+    /// Owner at the start of every round.
+    pub initial_team: Team,
 }
 
+/// Counts captures until the next captured cell becomes an item cell.
+#[derive(Resource, Clone, Copy, Debug, Default, Hash)]
+pub struct ItemDropCounter {
+    captures: usize,
+}
+
+/// Cells captured by each player in the current round.
+#[derive(Resource, Clone, Debug, Hash)]
+pub struct CaptureCount(pub Vec<usize>);
+
 #[derive(Message)]
 pub struct CellClicked {
     pub cell: Entity,
     pub team: Team,
 }
 
+/// Turns the camera so the lane of the local player is at the bottom of the screen.
 fn rotate(
     mut camera: Single<&mut Transform, With<Camera>>,
     local_players: Res<LocalPlayers>,
-    mut done: Local<bool>,
+    current_level: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
 ) {
-    if *done {
-        return;
-    }
-    let Some(flag) = local_players.0.first().map(|x| *x == 1) else {
-        warn!("No local player found, cannot rotate camera.");
+    let Some(handle) = local_players.0.first() else {
+        return; // the session has not reported its local players yet
+    };
+    let Some(lane) = levels
+        .get(&current_level.0)
+        .and_then(|level| level.lane(Team(*handle)))
+    else {
+        warn!("No lane found for the local player, cannot rotate camera.");
         return;
     };
-    if flag {
-        camera.rotate_z(std::f32::consts::PI);
+    let rotation = Quat::from_rotation_z(lane.side.camera_angle());
+    if camera.rotation != rotation {
+        camera.rotation = rotation;
     }
-    *done = true;
 }
 
-fn setup_field(mut commands: Commands) {
+fn setup_field(
+    mut commands: Commands,
+    current_level: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
+) {
+    let Some(level) = levels.get(&current_level.0) else {
+        error!("Current level is not loaded, cannot set up field.");
+        return;
+    };
+
+    commands.insert_resource(ItemDropCounter::default());
+    commands.insert_resource(CaptureCount(vec![0; level.num_players()]));
+
     // spawn cells
-    for i in 0..2 {
-        for x in -FIELD_WIDTH / 2..FIELD_WIDTH / 2 {
-            for y in 0..FIELD_HEIGHT {
-                let team = Team(i);
-                commands
-                    .spawn((
-                        Cell {
-                            half_size: Vec2::splat(CELL_SIZE / 2.),
-                        },
-                        team,
-                        Sprite::from_color(
-                            Color::hsl(team.hue(), 0.6, 0.7),
-                            Vec2::splat(CELL_SIZE),
-                        ),
-                        Transform::from_xyz(
-                            (x as f32 + 0.5) * CELL_SIZE,
-                            ((2. * i as f32 - 1.) * (y as f32 + 0.5)) * CELL_SIZE,
-                            5.,
-                        ),
-                        children![(
-                            Sprite::from_color(
-                                Color::hsl(team.hue(), 0.8, 0.7),
-                                Vec2::splat(CELL_SIZE - CELL_THICKNESS)
-                            ),
-                            Transform::from_xyz(0., 0., 1.),
-                        )],
-                    ))
-                    .add_rollback();
-            }
-        }
+    for (position, team) in level.cells() {
+        commands
+            .spawn((
+                Cell {
+                    half_size: FixedVec2::splat(Fixed::from_f32(CELL_SIZE / 2.)),
+                    initial_team: team,
+                },
+                team,
+                Sprite::from_color(Color::hsl(team.hue(), 0.6, 0.7), Vec2::splat(CELL_SIZE)),
+                Position(FixedVec2::from_vec2(position)),
+                Transform::from_translation(position.extend(5.)),
+                children![(
+                    Sprite::from_color(
+                        Color::hsl(team.hue(), 0.8, 0.7),
+                        Vec2::splat(CELL_SIZE - CELL_THICKNESS)
+                    ),
+                    Transform::from_xyz(0., 0., 1.),
+                )],
+            ))
+            .add_rollback();
     }
 
     // spawn walls
-    let wall_thickness = 1000.;
-    let wall_width = FIELD_WIDTH as f32 * CELL_SIZE;
-    let wall_height = FIELD_HEIGHT as f32 * CELL_SIZE * 2.;
-
-    let half_size = Vec2::new(wall_width, wall_thickness) / 2.;
-
-    commands.spawn((
-        Wall { half_size },
-        Team(1),
-        Sprite::from_color(
-            Color::srgb(0.3, 0.3, 0.3),
-            Vec2::new(wall_width, wall_thickness),
-        ),
-        Transform::from_xyz(0., (wall_height + wall_thickness) / 2., 6.),
-    ));
-    commands.spawn((
-        Wall { half_size },
-        Team(0),
-        Sprite::from_color(
-            Color::srgb(0.3, 0.3, 0.3),
-            Vec2::new(wall_width, wall_thickness),
-        ),
-        Transform::from_xyz(0., -(wall_height + wall_thickness) / 2., 6.),
-    ));
-
-    let half_size = Vec2::new(wall_thickness, wall_height + wall_thickness) / 2.;
-
-    commands.spawn((
-        Wall { half_size },
-        Sprite::from_color(
-            Color::srgb(0.3, 0.3, 0.3),
-            Vec2::new(wall_thickness, wall_height + wall_thickness),
-        ),
-        Transform::from_xyz(-(wall_width + wall_thickness) / 2., 0., 6.),
-    ));
-    commands.spawn((
-        Wall { half_size },
-        Sprite::from_color(
-            Color::srgb(0.3, 0.3, 0.3),
-            Vec2::new(wall_thickness, wall_height + wall_thickness),
-        ),
-        Transform::from_xyz((wall_width + wall_thickness) / 2., 0., 6.),
-    ));
+    for wall in &level.walls {
+        let size = Vec2::from(wall.size);
+        let mut entity = commands.spawn((
+            Wall {
+                half_size: FixedVec2::from_vec2(size / 2.),
+            },
+            Sprite::from_color(WALL_COLOR, size),
+            Position(FixedVec2::from_vec2(Vec2::from(wall.center))),
+            Transform::from_translation(Vec2::from(wall.center).extend(6.)),
+        ));
+        if let Some(team) = wall.team {
+            entity.insert(Team(team));
+        }
+    }
 }
 
 pub fn toggle_cell(
     mut q_cell: Query<&mut Team, With<Cell>>,
     mut q_click: MessageReader<CellClicked>,
-    mut count: Local<Count>,
+    mut counter: ResMut<ItemDropCounter>,
+    mut captures: ResMut<CaptureCount>,
 ) {
     for event in q_click.read() {
         if let Ok(mut team) = q_cell.get_mut(event.cell) {
-            if 10 <= count.0 {
+            if let Some(count) = captures.0.get_mut(event.team.0) {
+                *count += 1;
+            }
+            if ITEM_DROP_INTERVAL <= counter.captures {
                 *team = Team::ITEM;
-                count.0 = 0;
+                counter.captures = 0;
             } else {
                 *team = event.team;
             }
-            count.0 += 1;
+            counter.captures += 1;
         }
     }
 }
 
+fn reset_field(
+    q_cell: Query<(&Cell, &mut Team)>,
+    mut counter: ResMut<ItemDropCounter>,
+    mut captures: ResMut<CaptureCount>,
+) {
+    for (cell, mut team) in q_cell {
+        team.set_if_neq(cell.initial_team);
+    }
+    *counter = ItemDropCounter::default();
+    captures.0.fill(0);
+}
+
 #[allow(clippy::type_complexity)]
 fn update_cell_color(
     q_cell: Query<(&Children, &Team, &mut Sprite), (With<Cell>, Changed<Team>)>,
@@ -0,0 +1,404 @@
+//! Fixed-point numbers used by the rollback simulation.
+//!
+//! Float results can differ between native and wasm32 builds, which desyncs GGRS.
+//! Everything here is plain integer math, so both peers compute bit-identical states.
+
+use std::{
+    ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+
+/// Signed 48.16 fixed-point number.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed(i64);
+
+impl Fixed {
+    pub const FRAC_BITS: u32 = 16;
+    pub const MIN: Self = Self(i64::MIN);
+    pub const MAX: Self = Self(i64::MAX);
+    pub const ZERO: Self = Self(0);
+    pub const ONE: Self = Self(1 << Self::FRAC_BITS);
+    pub const PI: Self = Self(205_887);
+    pub const FRAC_PI_2: Self = Self(102_944);
+
+    pub const fn from_int(value: i64) -> Self {
+        Self(value << Self::FRAC_BITS)
+    }
+
+    /// `numerator / denominator`, rounded toward zero.
+    pub const fn from_ratio(numerator: i64, denominator: i64) -> Self {
+        Self((numerator << Self::FRAC_BITS) / denominator)
+    }
+
+    /// Converting is deterministic, so this is safe for constants, level files and rules.
+    pub fn from_f32(value: f32) -> Self {
+        Self((value as f64 * (1 << Self::FRAC_BITS) as f64).round() as i64)
+    }
+
+    pub fn from_duration(duration: Duration) -> Self {
+        Self(((duration.as_nanos() << Self::FRAC_BITS) / 1_000_000_000) as i64)
+    }
+
+    /// Only for rendering, never feed the result back into the simulation.
+    pub fn to_f32(self) -> f32 {
+        self.0 as f32 / (1 << Self::FRAC_BITS) as f32
+    }
+
+    pub fn abs(self) -> Self {
+        Self(self.0.saturating_abs())
+    }
+
+    pub fn signum(self) -> Self {
+        Self::from_int(self.0.signum())
+    }
+
+    pub fn sqrt(self) -> Self {
+        if self.0 <= 0 {
+            return Self::ZERO;
+        }
+        Self(((self.0 as u128) << Self::FRAC_BITS).isqrt() as i64)
+    }
+
+    pub fn sin(self) -> Self {
+        let two_pi = Self::PI.0 * 2;
+        let mut x = Self(self.0.rem_euclid(two_pi));
+        if Self::PI < x {
+            x -= Self(two_pi);
+        }
+        // sin(x) = sin(π - x) keeps the series in its accurate range
+        if Self::FRAC_PI_2 < x {
+            x = Self::PI - x;
+        } else if x < -Self::FRAC_PI_2 {
+            x = -Self::PI - x;
+        }
+
+        // Taylor series up to x^9
+        let x2 = x * x;
+        let mut term = x;
+        let mut sum = x;
+        for denominator in [2 * 3, 4 * 5, 6 * 7, 8 * 9] {
+            term = -(term * x2) / Self::from_int(denominator);
+            sum += term;
+        }
+        sum
+    }
+
+    pub fn cos(self) -> Self {
+        (self + Self::FRAC_PI_2).sin()
+    }
+}
+
+fn saturate(value: i128) -> i64 {
+    value.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+impl Add for Fixed {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(self.0.saturating_neg())
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self(saturate(
+            (self.0 as i128 * rhs.0 as i128) >> Self::FRAC_BITS,
+        ))
+    }
+}
+
+impl MulAssign for Fixed {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+impl Div for Fixed {
+    type Output = Self;
+
+    /// Dividing by zero saturates instead of panicking, so a degenerate state cannot crash one peer.
+    fn div(self, rhs: Self) -> Self {
+        if rhs == Self::ZERO {
+            return match self.0.signum() {
+                1 => Self::MAX,
+                -1 => Self::MIN,
+                _ => Self::ZERO,
+            };
+        }
+        Self(saturate(
+            ((self.0 as i128) << Self::FRAC_BITS) / rhs.0 as i128,
+        ))
+    }
+}
+
+/// Two-dimensional vector of [`Fixed`] numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FixedVec2 {
+    pub x: Fixed,
+    pub y: Fixed,
+}
+
+impl FixedVec2 {
+    pub const ZERO: Self = Self::new(Fixed::ZERO, Fixed::ZERO);
+
+    pub const fn new(x: Fixed, y: Fixed) -> Self {
+        Self { x, y }
+    }
+
+    pub const fn splat(value: Fixed) -> Self {
+        Self::new(value, value)
+    }
+
+    pub fn from_vec2(value: Vec2) -> Self {
+        Self::new(Fixed::from_f32(value.x), Fixed::from_f32(value.y))
+    }
+
+    /// Only for rendering, never feed the result back into the simulation.
+    pub fn to_vec2(self) -> Vec2 {
+        Vec2::new(self.x.to_f32(), self.y.to_f32())
+    }
+
+    pub fn dot(self, rhs: Self) -> Fixed {
+        self.x * rhs.x + self.y * rhs.y
+    }
+
+    /// Computed without squaring in fixed-point, so it does not overflow for large vectors.
+    pub fn length(self) -> Fixed {
+        let x = self.x.0 as i128;
+        let y = self.y.0 as i128;
+        Fixed(saturate((x * x).saturating_add(y * y).isqrt()))
+    }
+
+    pub fn normalize_or_zero(self) -> Self {
+        if self == Self::ZERO {
+            return Self::ZERO;
+        }
+        // the direction does not depend on the scale, so tiny vectors are scaled up to keep their precision
+        let largest = self.x.0.unsigned_abs().max(self.y.0.unsigned_abs());
+        let shift = largest.leading_zeros().saturating_sub(34);
+        let scaled = Self::new(Fixed(self.x.0 << shift), Fixed(self.y.0 << shift));
+        scaled / scaled.length()
+    }
+
+    pub fn reflect(self, normal: Self) -> Self {
+        self - normal * (Fixed::from_int(2) * self.dot(normal))
+    }
+
+    pub fn clamp(self, min: Self, max: Self) -> Self {
+        Self::new(self.x.clamp(min.x, max.x), self.y.clamp(min.y, max.y))
+    }
+
+    /// Rotates the vector counterclockwise by `angle` radians.
+    pub fn rotate(self, angle: Fixed) -> Self {
+        let (sin, cos) = (angle.sin(), angle.cos());
+        Self::new(self.x * cos - self.y * sin, self.x * sin + self.y * cos)
+    }
+}
+
+impl Add for FixedVec2 {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+
+impl AddAssign for FixedVec2 {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for FixedVec2 {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.x - rhs.x, self.y - rhs.y)
+    }
+}
+
+impl SubAssign for FixedVec2 {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl Neg for FixedVec2 {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(-self.x, -self.y)
+    }
+}
+
+impl Mul<Fixed> for FixedVec2 {
+    type Output = Self;
+
+    fn mul(self, rhs: Fixed) -> Self {
+        Self::new(self.x * rhs, self.y * rhs)
+    }
+}
+
+impl MulAssign<Fixed> for FixedVec2 {
+    fn mul_assign(&mut self, rhs: Fixed) {
+        *self = *self * rhs;
+    }
+}
+
+impl Div<Fixed> for FixedVec2 {
+    type Output = Self;
+
+    fn div(self, rhs: Fixed) -> Self {
+        Self::new(self.x / rhs, self.y / rhs)
+    }
+}
+
+/// Axis-aligned bounding box in simulation space.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedAabb {
+    pub min: FixedVec2,
+    pub max: FixedVec2,
+}
+
+impl FixedAabb {
+    pub fn new(center: FixedVec2, half_size: FixedVec2) -> Self {
+        Self {
+            min: center - half_size,
+            max: center + half_size,
+        }
+    }
+
+    pub fn closest_point(&self, point: FixedVec2) -> FixedVec2 {
+        point.clamp(self.min, self.max)
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: Fixed, expected: f64, tolerance: f64) -> bool {
+        (actual.0 as f64 / Fixed::ONE.0 as f64 - expected).abs() <= tolerance
+    }
+
+    #[test]
+    fn sin_and_cos_match_f64() {
+        for step in -20_000..=20_000 {
+            let angle = Fixed::from_ratio(step, 1000);
+            let x = angle.0 as f64 / Fixed::ONE.0 as f64;
+            assert!(
+                close(angle.sin(), x.sin(), 1e-4),
+                "sin({x}) = {:?}",
+                angle.sin()
+            );
+            assert!(
+                close(angle.cos(), x.cos(), 1e-4),
+                "cos({x}) = {:?}",
+                angle.cos()
+            );
+        }
+    }
+
+    #[test]
+    fn sqrt_matches_f64() {
+        for value in [
+            0.0001, 0.01, 0.5, 1.0, 2.0, 3.0, 10.0, 1000.0, 123_456.7, 1e9,
+        ] {
+            let fixed = Fixed::from_f32(value as f32);
+            let exact = (fixed.0 as f64 / Fixed::ONE.0 as f64).sqrt();
+            assert!(close(fixed.sqrt(), exact, 1e-4), "sqrt({value})");
+        }
+        assert_eq!(Fixed::ZERO.sqrt(), Fixed::ZERO);
+        assert_eq!(Fixed::from_int(-4).sqrt(), Fixed::ZERO);
+    }
+
+    #[test]
+    fn arithmetic_saturates() {
+        assert_eq!(Fixed::MAX + Fixed::ONE, Fixed::MAX);
+        assert_eq!(Fixed::MIN - Fixed::ONE, Fixed::MIN);
+        assert_eq!(Fixed::MAX * Fixed::from_int(2), Fixed::MAX);
+        assert_eq!(Fixed::MAX * Fixed::from_int(-2), Fixed::MIN);
+        assert_eq!(Fixed::MAX / Fixed::from_ratio(1, 2), Fixed::MAX);
+        assert_eq!(-Fixed::MIN, Fixed::MAX);
+        assert_eq!(Fixed::MIN.abs(), Fixed::MAX);
+    }
+
+    #[test]
+    fn divide_by_zero_and_tiny_values() {
+        assert_eq!(Fixed::ONE / Fixed::ZERO, Fixed::MAX);
+        assert_eq!(-Fixed::ONE / Fixed::ZERO, Fixed::MIN);
+        assert_eq!(Fixed::ZERO / Fixed::ZERO, Fixed::ZERO);
+        assert_eq!(Fixed::ONE / Fixed(1), Fixed(1 << 32));
+        assert_eq!(Fixed(1) / Fixed::from_int(2), Fixed::ZERO);
+        assert_eq!(
+            Fixed::from_int(3) / Fixed::from_int(-2),
+            -Fixed::from_ratio(3, 2)
+        );
+    }
+
+    #[test]
+    fn normalize_keeps_unit_length() {
+        assert_eq!(FixedVec2::ZERO.normalize_or_zero(), FixedVec2::ZERO);
+        let vectors = [
+            (1, 0),
+            (1, 1),
+            (0, -1),
+            (3, 4),
+            (-5, 2),
+            (300 << 16, 7 << 16),
+            (1 << 62, 1 << 62),
+            (i64::MIN, 0),
+        ];
+        for (x, y) in vectors {
+            let vector = FixedVec2::new(Fixed(x), Fixed(y));
+            let normalized = vector.normalize_or_zero();
+            assert!(
+                close(normalized.length(), 1.0, 1e-4),
+                "{vector:?} -> {normalized:?}"
+            );
+            // the direction is kept
+            assert_eq!(normalized.x.0.signum(), x.signum());
+            assert_eq!(normalized.y.0.signum(), y.signum());
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use std::{collections::HashMap, time::Duration};
+
+use bevy::prelude::*;
+use bevy_ggrs::{LocalPlayers, prelude::*};
+
+use crate::game::{
+    GameState,
+    ball::{Ball, Velocity},
+    components::Team,
+    field::{WALL_COLOR, Wall},
+    fixed::{Fixed, FixedVec2},
+    paddle::{PADDLE_WIDTH, Paddle},
+    rules::MatchRules,
+};
+
+const MAX_BALL_SPEED: Fixed = Fixed::from_int(60000000);
+const ENLARGE_PADDLE_MULTIPLIER: Fixed = Fixed::from_ratio(3, 2);
+const MAX_PADDLE_HALF_WIDTH: Fixed = Fixed::from_ratio(500, 3); // a third of the classic field width
+const SHRINK_PADDLE_MULTIPLIER: Fixed = Fixed::from_ratio(2, 3);
+const SLOW_BALLS_MULTIPLIER: Fixed = Fixed::from_ratio(2, 3);
+
+/// Temporary effect of a collected item on the team it is attached to.
+#[derive(Component, Clone, Debug)]
+pub struct ActiveEffect {
+    pub kind: EffectKind,
+    pub timer: Timer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EffectKind {
+    EnlargePaddle,
+    SpeedUp,
+    ShrinkPaddle,
+    SlowBalls,
+    /// Balls stop on the own paddle until the player releases them.
+    StickyPaddle,
+    /// Balls capture cells without bouncing off them.
+    PiercingBall,
+    /// The own wall bounces balls back instead of losing them.
+    Shield,
+    ReversedControls,
+}
+
+/// What happens when an effect is collected while the same effect is still active.
+#[derive(Clone, Copy, Debug)]
+pub enum Stacking {
+    /// Add the duration to the running effect.
+    Extend,
+    /// Restart the running effect with the new duration.
+    Refresh,
+    /// Run another instance side by side, refreshing the oldest one once `max` are running.
+    Stack { max: usize },
+}
+
+impl EffectKind {
+    pub fn stacking(self) -> Stacking {
+        match self {
+            EffectKind::EnlargePaddle | EffectKind::Shield => Stacking::Extend,
+            EffectKind::SpeedUp => Stacking::Stack { max: 3 },
+            EffectKind::ShrinkPaddle
+            | EffectKind::SlowBalls
+            | EffectKind::StickyPaddle
+            | EffectKind::PiercingBall
+            | EffectKind::ReversedControls => Stacking::Refresh,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            EffectKind::EnlargePaddle => "Enlarge paddle",
+            EffectKind::SpeedUp => "Speed up",
+            EffectKind::ShrinkPaddle => "Shrunk paddle",
+            EffectKind::SlowBalls => "Slow balls",
+            EffectKind::StickyPaddle => "Sticky paddle",
+            EffectKind::PiercingBall => "Piercing ball",
+            EffectKind::Shield => "Shield",
+            EffectKind::ReversedControls => "Reversed controls",
+        }
+    }
+}
+
+/// Speed multiplier of the running effects a ball last got, so only a change of the effects rescales it.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct SpeedFactor(pub Fixed);
+
+impl Default for SpeedFactor {
+    fn default() -> Self {
+        Self(Fixed::ONE)
+    }
+}
+
+#[derive(Component)]
+pub(super) struct EffectHud;
+
+/// Number of running instances of `kind` on `team`.
+pub fn stacks(q_effects: &Query<(&ActiveEffect, &Team)>, team: &Team, kind: EffectKind) -> usize {
+    q_effects
+        .iter()
+        .filter(|(effect, effect_team)| effect.kind == kind && *effect_team == team)
+        .count()
+}
+
+pub fn is_active(q_effects: &Query<(&ActiveEffect, &Team)>, team: &Team, kind: EffectKind) -> bool {
+    0 < stacks(q_effects, team, kind)
+}
+
+/// Adds an effect collected this tick to `pending`, or stacks it onto a running or pending one.
+///
+/// Pending effects count as running, so several pickups in one tick stack like pickups in a row.
+pub fn add_effect(
+    q_effects: &mut Query<(&mut ActiveEffect, &Team)>,
+    pending: &mut Vec<(ActiveEffect, Team)>,
+    team: Team,
+    kind: EffectKind,
+    duration: Duration,
+) {
+    let mut running = q_effects
+        .iter_mut()
+        .map(|(effect, effect_team)| (effect.into_inner(), effect_team))
+        .chain(pending.iter_mut().map(|(effect, team)| (effect, &*team)))
+        .filter(|(effect, effect_team)| effect.kind == kind && **effect_team == team)
+        .map(|(effect, _)| effect)
+        .collect::<Vec<_>>();
+
+    match kind.stacking() {
+        Stacking::Extend if !running.is_empty() => {
+            for effect in &mut running {
+                let extended = effect.timer.duration() + duration;
+                effect.timer.set_duration(extended);
+            }
+            return;
+        }
+        Stacking::Stack { max } if max <= running.len() => {
+            if let Some(oldest) = running
+                .iter_mut()
+                .min_by_key(|effect| effect.timer.remaining())
+            {
+                oldest.timer.reset();
+            }
+            return;
+        }
+        Stacking::Refresh if !running.is_empty() => {
+            for effect in &mut running {
+                effect.timer = Timer::new(duration, TimerMode::Once);
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    pending.push((
+        ActiveEffect {
+            kind,
+            timer: Timer::new(duration, TimerMode::Once),
+        },
+        team,
+    ));
+}
+
+/// Starts the effects [`add_effect`] left pending.
+pub fn spawn_effects(commands: &mut Commands, pending: Vec<(ActiveEffect, Team)>) {
+    for (effect, team) in pending {
+        commands
+            .spawn((effect, team, DespawnOnExit(GameState::InGame)))
+            .add_rollback();
+    }
+}
+
+pub(super) fn tick_effects(
+    mut commands: Commands,
+    q_effects: Query<(Entity, &mut ActiveEffect)>,
+    time: Res<Time>,
+) {
+    for (entity, mut effect) in q_effects {
+        effect.timer.tick(time.delta());
+        if effect.timer.is_finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Recomputes paddle sizes and ball speed factors from the running effects,
+/// so an effect is reverted as soon as it is gone.
+pub(super) fn apply_effects(
+    q_effects: Query<(&ActiveEffect, &Team)>,
+    q_balls: Query<(&Team, &mut Velocity, &mut SpeedFactor), With<Ball>>,
+    q_paddles: Query<(&Team, &mut Paddle)>,
+    rules: Res<MatchRules>,
+) {
+    for (team, mut velocity, mut applied) in q_balls {
+        let mut factor = Fixed::ONE;
+        for _ in 0..stacks(&q_effects, team, EffectKind::SpeedUp) {
+            factor *= Fixed::from_f32(rules.speed_up_multiplier);
+        }
+        if is_active(&q_effects, team, EffectKind::SlowBalls) {
+            factor *= SLOW_BALLS_MULTIPLIER;
+        }
+        // otherwise the ball keeps its speed, launches and all
+        if applied.0 != factor {
+            let speed = velocity.length() / applied.0 * factor;
+            velocity.0 = velocity.normalize_or_zero() * speed.min(MAX_BALL_SPEED);
+            applied.0 = factor;
+        }
+    }
+
+    for (team, mut paddle) in q_paddles {
+        let mut half_width = Fixed::from_f32(PADDLE_WIDTH / 2.);
+        if is_active(&q_effects, team, EffectKind::EnlargePaddle) {
+            half_width = (half_width * ENLARGE_PADDLE_MULTIPLIER).min(MAX_PADDLE_HALF_WIDTH);
+        }
+        if is_active(&q_effects, team, EffectKind::ShrinkPaddle) {
+            half_width *= SHRINK_PADDLE_MULTIPLIER;
+        }
+        if paddle.half_width != half_width {
+            paddle.half_width = half_width;
+        }
+    }
+}
+
+/// Resizes the mesh of every paddle whose size differs from the one last drawn.
+pub(super) fn update_paddle_meshes(
+    q_paddles: Query<(Entity, &Paddle, &Mesh2d), Changed<Paddle>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut drawn: Local<HashMap<Entity, FixedVec2>>,
+) {
+    for (entity, paddle, mesh2d) in q_paddles {
+        let half_size = paddle.half_size();
+        // rollbacks mark every paddle changed, mostly without a new size
+        if drawn.insert(entity, half_size) == Some(half_size) {
+            continue;
+        }
+        if let Some(mesh) = meshes.get_mut(&mesh2d.0) {
+            *mesh = Rectangle::from_size(2. * half_size.to_vec2()).into();
+        }
+    }
+}
+
+/// Paints shielded walls in the team colour.
+pub(super) fn update_wall_shields(
+    q_effects: Query<(&ActiveEffect, &Team)>,
+    q_walls: Query<(&Team, &mut Sprite), With<Wall>>,
+) {
+    for (team, mut sprite) in q_walls {
+        let color = if is_active(&q_effects, team, EffectKind::Shield) {
+            Color::hsl(team.hue(), 0.8, 0.5)
+        } else {
+            WALL_COLOR
+        };
+        if sprite.color != color {
+            sprite.color = color;
+        }
+    }
+}
+
+pub(super) fn setup_effect_hud(mut commands: Commands) {
+    commands.spawn((
+        EffectHud,
+        DespawnOnExit(GameState::InGame),
+        Text::default(),
+        TextFont::from_font_size(18.0),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        },
+        ZIndex(1000),
+    ));
+}
+
+pub(super) fn update_effect_hud(
+    q_effects: Query<(&ActiveEffect, &Team)>,
+    local_players: Res<LocalPlayers>,
+    mut hud: Single<&mut Text, With<EffectHud>>,
+) {
+    // spectators have no local player
+    let local_team = local_players.0.first().copied();
+    let mut lines = q_effects
+        .iter()
+        .map(|(effect, team)| {
+            let owner = if Some(team.0) == local_team {
+                "You".to_string()
+            } else {
+                format!("Player {}", team.0 + 1)
+            };
+            format!(
+                "{owner}: {} {:.1}s",
+                effect.kind.label(),
+                effect.timer.remaining_secs()
+            )
+        })
+        .collect::<Vec<_>>();
+    lines.sort();
+    hud.0 = lines.join("\n");
+}
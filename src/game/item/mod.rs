@@ -1,26 +1,27 @@
-use bevy::{
-    color::palettes::css,
-    math::bounding::{Aabb2d, IntersectsVolume},
-    prelude::*,
-};
+use std::time::Duration;
+
+use bevy::{color::palettes::css, ecs::system::SystemParam, prelude::*};
 use bevy_ggrs::prelude::*;
+use effect::{ActiveEffect, EffectKind, SpeedFactor};
 
 use super::{
     GameState,
     ball::{BALL_RADIUS, Ball, Velocity},
-    components::{Count, Team},
-    field::{CELL_SIZE, Cell, CellClicked, FIELD_WIDTH, toggle_cell},
-    paddle::{PADDLE_HEIGHT, Paddle},
+    components::{Position, Team},
+    field::{Cell, CellClicked, toggle_cell},
+    fixed::{Fixed, FixedAabb, FixedVec2},
+    paddle::Paddle,
+    rng::MatchRng,
+    round::RoundReset,
+    rules::MatchRules,
 };
 
-const ITEM_FALL_SPEED: f32 = 150.0;
-const MULTI_BALL_COUNT: i32 = 2;
-const MAX_BALL_SPEED: f32 = 60000000.0;
+const ITEM_FALL_SPEED: Fixed = Fixed::from_int(150);
 const MAX_BALL_COUNT: usize = 20;
-const SPEED_UP_MULTIPLIER: f32 = 1.2;
-const ENLARGE_PADDLE_MULTIPLIER: f32 = 1.5;
 const ITEM_SIZE: f32 = 20.0;
 
+pub mod effect;
+
 pub struct ItemPlugin;
 
 impl Plugin for ItemPlugin {
@@ -32,12 +33,27 @@ impl Plugin for ItemPlugin {
                 move_items,
                 check_paddle_collision,
                 apply_item_effect,
+                effect::tick_effects,
+                effect::apply_effects,
             )
                 .chain()
                 .before(toggle_cell)
                 .run_if(in_state(GameState::InGame)),
         )
+        .add_systems(GgrsSchedule, clear_items.in_set(RoundReset))
+        .add_systems(OnEnter(GameState::InGame), effect::setup_effect_hud)
+        .add_systems(
+            Update,
+            (
+                effect::update_paddle_meshes,
+                effect::update_wall_shields,
+                effect::update_effect_hud,
+            )
+                .run_if(in_state(GameState::InGame)),
+        )
         .rollback_component_with_copy::<Item>()
+        .rollback_component_with_clone::<ActiveEffect>()
+        .rollback_component_with_copy::<SpeedFactor>()
         .add_message::<ItemCollected>();
     }
 }
@@ -47,6 +63,68 @@ pub enum ItemType {
     EnlargePaddle,
     SpeedUp,
     MultiBall,
+    ShrinkOpponentPaddle,
+    SlowOpponentBalls,
+    StickyPaddle,
+    PiercingBall,
+    Shield,
+    ReverseOpponentControls,
+}
+
+impl ItemType {
+    const ALL: [Self; 9] = [
+        Self::EnlargePaddle,
+        Self::SpeedUp,
+        Self::MultiBall,
+        Self::ShrinkOpponentPaddle,
+        Self::SlowOpponentBalls,
+        Self::StickyPaddle,
+        Self::PiercingBall,
+        Self::Shield,
+        Self::ReverseOpponentControls,
+    ];
+
+    /// The effect started by the item, and whether it hits the opponents instead of the collector.
+    fn effect(self) -> Option<(EffectKind, bool)> {
+        match self {
+            Self::EnlargePaddle => Some((EffectKind::EnlargePaddle, false)),
+            Self::SpeedUp => Some((EffectKind::SpeedUp, false)),
+            Self::MultiBall => None,
+            Self::ShrinkOpponentPaddle => Some((EffectKind::ShrinkPaddle, true)),
+            Self::SlowOpponentBalls => Some((EffectKind::SlowBalls, true)),
+            Self::StickyPaddle => Some((EffectKind::StickyPaddle, false)),
+            Self::PiercingBall => Some((EffectKind::PiercingBall, false)),
+            Self::Shield => Some((EffectKind::Shield, false)),
+            Self::ReverseOpponentControls => Some((EffectKind::ReversedControls, true)),
+        }
+    }
+
+    /// Every item has its own colour and shape, so players can tell them apart while they fall.
+    fn look(self) -> (Color, Mesh) {
+        let radius = ITEM_SIZE / 2.;
+        let (color, mesh) = match self {
+            Self::EnlargePaddle => (css::LIME, Mesh::from(Rectangle::new(ITEM_SIZE, radius))),
+            Self::SpeedUp => (css::ORANGE, Mesh::from(Circle::new(radius))),
+            Self::MultiBall => (css::YELLOW, Mesh::from(Annulus::new(radius / 2., radius))),
+            Self::ShrinkOpponentPaddle => {
+                (css::CRIMSON, Mesh::from(Rhombus::new(ITEM_SIZE, radius)))
+            }
+            Self::SlowOpponentBalls => (
+                css::DEEP_SKY_BLUE,
+                Mesh::from(RegularPolygon::new(radius, 3)),
+            ),
+            Self::StickyPaddle => (
+                css::MEDIUM_PURPLE,
+                Mesh::from(Capsule2d::new(radius / 2., radius)),
+            ),
+            Self::PiercingBall => (css::DARK_RED, Mesh::from(RegularPolygon::new(radius, 4))),
+            Self::Shield => (css::SILVER, Mesh::from(RegularPolygon::new(radius, 6))),
+            Self::ReverseOpponentControls => {
+                (css::MAGENTA, Mesh::from(RegularPolygon::new(radius, 5)))
+            }
+        };
+        (color.into(), mesh)
+    }
 }
 
 #[derive(Component, Clone, Copy, Debug)]
@@ -63,58 +141,68 @@ struct ItemCollected {
 pub fn spawn_item(
     mut commands: Commands,
     mut ev: MessageReader<CellClicked>,
-    q_cell: Query<(&Transform, &Team), With<Cell>>,
-    mut count: Local<Count>,
+    q_cell: Query<(&Position, &Team), With<Cell>>,
+    mut rng: ResMut<MatchRng>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
     for ev in ev.read() {
-        let Ok((cell_transform, cell_team)) = q_cell.get(ev.cell) else {
+        let Ok((cell_position, cell_team)) = q_cell.get(ev.cell) else {
             continue;
         };
         if *cell_team != Team::ITEM {
             continue;
         }
-        let item_type = match count.0 % 3 {
-            0 => ItemType::EnlargePaddle,
-            1 => ItemType::SpeedUp,
-            _ => ItemType::MultiBall,
-        };
-        count.0 += 1;
+        let item_type = ItemType::ALL[rng.range(0..ItemType::ALL.len())];
+        let (color, mesh) = item_type.look();
 
         commands
             .spawn((
                 Item { item_type },
                 ev.team,
-                Sprite::from_color(css::YELLOW, Vec2::splat(ITEM_SIZE)),
-                Transform::from_translation(cell_transform.translation.with_z(7.)),
+                Mesh2d(meshes.add(mesh)),
+                MeshMaterial2d(materials.add(color)),
+                *cell_position,
+                Transform::from_translation(cell_position.to_vec2().extend(7.)),
             ))
             .add_rollback();
     }
 }
 
-fn move_items(q_items: Query<(&Team, &mut Transform), With<Item>>, time: Res<Time>) {
-    for (team, mut transform) in q_items {
-        let direction = if team.0 == 0 { -1.0 } else { 1.0 };
-        transform.translation.y += direction * ITEM_FALL_SPEED * time.delta_secs();
+/// Items fall towards the paddle of the team that dropped them.
+fn move_items(
+    q_items: Query<(&Team, &mut Position), With<Item>>,
+    q_paddles: Query<(&Paddle, &Team)>,
+    time: Res<Time>,
+) {
+    let delta = Fixed::from_duration(time.delta());
+    for (team, mut position) in q_items {
+        let Some((paddle, _)) = q_paddles
+            .iter()
+            .find(|(_, paddle_team)| *paddle_team == team)
+        else {
+            continue;
+        };
+        position.0 -= paddle.side.normal() * ITEM_FALL_SPEED * delta;
     }
 }
 
 fn check_paddle_collision(
     mut commands: Commands,
-    q_items: Query<(Entity, &Item, &Team, &Transform)>,
-    q_paddles: Query<(&Paddle, &Team, &Transform)>,
+    q_items: Query<(Entity, &Item, &Team, &Position)>,
+    q_paddles: Query<(&Paddle, &Team, &Position)>,
     mut ev_collision: MessageWriter<ItemCollected>,
 ) {
-    for (item_entity, item, team, item_transform) in q_items {
-        for (paddle, paddle_team, paddle_transform) in q_paddles {
+    for (item_entity, item, team, item_position) in q_items {
+        for (paddle, paddle_team, paddle_position) in q_paddles {
             if *team != *paddle_team {
                 continue;
             }
 
-            let paddle_aabb2 =
-                Aabb2d::new(paddle_transform.translation.truncate(), paddle.half_size);
-            let item_aabb2 = Aabb2d::new(
-                item_transform.translation.truncate(),
-                Vec2::splat(ITEM_SIZE),
+            let paddle_aabb2 = FixedAabb::new(paddle_position.0, paddle.half_size());
+            let item_aabb2 = FixedAabb::new(
+                item_position.0,
+                FixedVec2::splat(Fixed::from_f32(ITEM_SIZE)),
             );
 
             if paddle_aabb2.intersects(&item_aabb2) {
@@ -128,66 +216,85 @@ fn check_paddle_collision(
     }
 }
 
+/// Removes falling items and running effects, so every round starts without them.
+fn clear_items(
+    mut commands: Commands,
+    q_items: Query<Entity, Or<(With<Item>, With<ActiveEffect>)>>,
+) {
+    for entity in &q_items {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Assets for the balls spawned by a multi-ball.
+#[derive(SystemParam)]
+struct BallAssets<'w> {
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<ColorMaterial>>,
+}
+
 fn apply_item_effect(
     mut commands: Commands,
     mut ev_collected: MessageReader<ItemCollected>,
-    mut q_balls: Query<(&Ball, &Team, &Transform, &mut Velocity)>,
-    mut q_paddles: Query<(&mut Paddle, &mut Mesh2d, &Team)>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    q_balls: Query<(&Ball, &Team, &Position, &Velocity, &SpeedFactor)>,
+    q_paddles: Query<&Team, With<Paddle>>,
+    mut q_effects: Query<(&mut ActiveEffect, &Team)>,
+    mut assets: BallAssets,
+    rules: Res<MatchRules>,
 ) {
+    let effect_duration = Duration::from_secs_f32(rules.effect_duration_secs);
+    let mut pending = Vec::new();
     for ev in ev_collected.read() {
         match ev.item_type {
             ItemType::MultiBall => {
                 if MAX_BALL_COUNT <= q_balls.iter().count() {
                     continue;
                 }
-                for (ball, team, transform, velocity) in &q_balls {
+                for (ball, team, position, velocity, factor) in &q_balls {
                     if *team != ev.team {
                         continue;
                     }
 
-                    for i in 0..MULTI_BALL_COUNT {
-                        let angle = std::f32::consts::PI / 6.0 * (i as f32 - 0.5);
-                        let new_velocity = Vec2::new(
-                            velocity.x * angle.cos() - velocity.y * angle.sin(),
-                            velocity.x * angle.sin() + velocity.y * angle.cos(),
-                        );
+                    for i in 0..rules.multi_ball_count {
+                        // PI / 6 * (i - 0.5)
+                        let angle = Fixed::PI * Fixed::from_ratio(2 * i as i64 - 1, 12);
+                        let new_velocity = velocity.rotate(angle);
 
                         commands
                             .spawn((
                                 *ball,
                                 *team,
-                                Mesh2d(meshes.add(Mesh::from(Circle::new(BALL_RADIUS)))),
-                                MeshMaterial2d(materials.add(Color::BLACK)),
-                                Transform::from_translation(transform.translation),
+                                Mesh2d(assets.meshes.add(Mesh::from(Circle::new(BALL_RADIUS)))),
+                                MeshMaterial2d(assets.materials.add(Color::BLACK)),
+                                *position,
+                                Transform::from_translation(position.to_vec2().extend(10.)),
                                 Velocity(new_velocity),
+                                *factor,
                             ))
                             .add_rollback();
                     }
                 }
             }
-            ItemType::SpeedUp => {
-                for (_, team, _, mut velocity) in &mut q_balls {
-                    if *team == ev.team {
-                        velocity.0 *= SPEED_UP_MULTIPLIER;
-                        if MAX_BALL_SPEED.powf(2.) < velocity.length_squared() {
-                            velocity.0 = velocity.0.normalize() * MAX_BALL_SPEED;
+            item_type => {
+                let Some((kind, offensive)) = item_type.effect() else {
+                    continue;
+                };
+                let targets = q_paddles
+                    .iter()
+                    .filter(|team| {
+                        if offensive {
+                            !rules.allied(**team, ev.team)
+                        } else {
+                            **team == ev.team
                         }
-                    }
-                }
-            }
-            ItemType::EnlargePaddle => {
-                for (mut paddle, mut mesh2d, team) in &mut q_paddles {
-                    if *team == ev.team {
-                        paddle.half_size.x = (paddle.half_size.x * ENLARGE_PADDLE_MULTIPLIER)
-                            .min(FIELD_WIDTH as f32 * CELL_SIZE * 2. / 6.);
-                        *mesh2d = Mesh2d(
-                            meshes.add(Rectangle::new(2. * paddle.half_size.x, PADDLE_HEIGHT)),
-                        );
-                    }
+                    })
+                    .copied()
+                    .collect::<Vec<_>>();
+                for team in targets {
+                    effect::add_effect(&mut q_effects, &mut pending, team, kind, effect_duration);
                 }
             }
         }
     }
+    effect::spawn_effects(&mut commands, pending);
 }
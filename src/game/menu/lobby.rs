@@ -4,8 +4,18 @@ use bevy::{color::palettes::css::GRAY, prelude::*};
 use bevy_egui::{EguiContexts, EguiPrimaryContextPass, egui};
 
 use crate::{
-    args::Args,
-    game::{GameState, online::network_role::NetworkRole},
+    args::{Args, InputDelay},
+    game::{
+        GameState,
+        field::level::LEVELS,
+        online::{
+            network_role::NetworkRole,
+            room::is_spectator_invite,
+            room_browser::{RoomBrowser, RoomListOpen},
+            signalling::check_invite,
+        },
+        rules::{MatchRules, TeamMode, VictoryCondition},
+    },
 };
 
 const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
@@ -19,7 +29,11 @@ impl Plugin for LobbyPlugin {
         app.add_systems(OnEnter(GameState::Lobby), setup_lobby)
             .add_systems(
                 EguiPrimaryContextPass,
-                show_textbox.run_if(in_state(GameState::Lobby)),
+                (
+                    show_textbox,
+                    show_room_browser.run_if(resource_exists::<RoomBrowser>),
+                )
+                    .run_if(in_state(GameState::Lobby)),
             )
             .add_systems(Update, button_system.run_if(in_state(GameState::Lobby)));
     }
@@ -41,20 +55,158 @@ fn setup_lobby(mut commands: Commands) {
         .with_children(|parent| {
             spawn_button(parent, NetworkRole::Host);
             spawn_button(parent, NetworkRole::Client);
+            spawn_button(parent, NetworkRole::Spectator);
         });
 }
 
-fn show_textbox(mut context: EguiContexts, mut args: ResMut<Args>) {
+fn show_textbox(
+    mut context: EguiContexts,
+    mut args: ResMut<Args>,
+    mut rules: ResMut<MatchRules>,
+    mut room_list: ResMut<RoomListOpen>,
+) {
     egui::Area::new(egui::Id::new(0))
         .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
         .show(context.ctx_mut().unwrap(), |ui| {
-            ui.label("Enter Room ID:");
+            ui.label("Enter Room Code:");
             ui.add(
                 egui::TextEdit::singleline(&mut args.iroh)
-                    .hint_text("x".repeat(64))
+                    .hint_text("room code or invite link")
                     .font(egui::FontId::proportional(30.))
                     .desired_width(400.),
             );
+            if !args.iroh.trim().is_empty()
+                && let Err(reason) = check_invite(args.signalling, &args.iroh)
+            {
+                ui.colored_label(egui::Color32::LIGHT_RED, reason);
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            ui.checkbox(&mut args.lan, "LAN only (no internet)");
+            ui.collapsing("Network", |ui| {
+                let mut auto = args.input_delay == InputDelay::Auto;
+                ui.checkbox(&mut auto, "Pick the input delay from the ping");
+                let mut frames = match args.input_delay {
+                    InputDelay::Frames(frames) => frames,
+                    InputDelay::Auto => 2,
+                };
+                ui.add_enabled(
+                    !auto,
+                    egui::Slider::new(&mut frames, 0..=8).text("Input delay (frames)"),
+                );
+                args.input_delay = if auto {
+                    InputDelay::Auto
+                } else {
+                    InputDelay::Frames(frames)
+                };
+                ui.add(
+                    egui::Slider::new(&mut args.max_prediction, 0..=16)
+                        .text("Max prediction (frames)"),
+                );
+            });
+            ui.checkbox(&mut room_list.0, "Browse public rooms");
+            ui.checkbox(&mut args.public, "List the room publicly (host only)");
+            if args.public {
+                ui.add(egui::TextEdit::singleline(&mut args.name).hint_text("host name"));
+            }
+            ui.label("Level (host only):");
+            egui::ComboBox::from_id_salt("level")
+                .selected_text(&args.level)
+                .show_ui(ui, |ui| {
+                    for level in LEVELS {
+                        ui.selectable_value(&mut args.level, level.to_string(), *level);
+                    }
+                });
+            ui.collapsing("Rules (host only)", |ui| {
+                ui.add(
+                    egui::Slider::new(&mut rules.game_duration_secs, 30.0..=300.0)
+                        .text("Duration (s)"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut rules.first_ball_speed, 100.0..=600.0)
+                        .text("Ball speed"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut rules.paddle_speed, 100.0..=600.0).text("Paddle speed"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut rules.multi_ball_count, 1..=4).text("Multi ball count"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut rules.speed_up_multiplier, 1.0..=2.0)
+                        .text("Speed up multiplier"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut rules.effect_duration_secs, 3.0..=30.0)
+                        .text("Item effect duration (s)"),
+                );
+                egui::ComboBox::from_label("Victory condition")
+                    .selected_text(rules.victory.label())
+                    .show_ui(ui, |ui| {
+                        for victory in VictoryCondition::ALL {
+                            ui.selectable_value(&mut rules.victory, victory, victory.label());
+                        }
+                    });
+                if rules.victory == VictoryCondition::CellShare {
+                    ui.add(
+                        egui::Slider::new(&mut rules.cell_share_percent, 30..=90)
+                            .text("Cell share (%)"),
+                    );
+                }
+                ui.add(
+                    egui::Slider::new(&mut rules.rounds, 1..=7)
+                        .step_by(2.0)
+                        .text("Best of (rounds)"),
+                );
+                ui.checkbox(&mut rules.sudden_death, "Sudden death on a draw");
+                egui::ComboBox::from_label("Teams (4 player levels)")
+                    .selected_text(rules.team_mode.label())
+                    .show_ui(ui, |ui| {
+                        for mode in TeamMode::ALL {
+                            ui.selectable_value(&mut rules.team_mode, mode, mode.label());
+                        }
+                    });
+            });
+        });
+}
+
+fn show_room_browser(
+    mut commands: Commands,
+    mut context: EguiContexts,
+    mut args: ResMut<Args>,
+    browser: Res<RoomBrowser>,
+    mut room_list: ResMut<RoomListOpen>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    egui::Window::new("Open rooms")
+        .anchor(egui::Align2::LEFT_TOP, egui::vec2(10., 10.))
+        .resizable(false)
+        .open(&mut room_list.0)
+        .show(context.ctx_mut().unwrap(), |ui| {
+            let rooms = browser.rooms();
+            if browser.no_directory {
+                ui.label("No directory node known, start with --directory-peer <node id>");
+            } else if rooms.is_empty() {
+                ui.label("No public rooms found yet");
+            }
+            for record in rooms {
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "{} - {} ({}) - {}/{} - best of {}",
+                        record.host_name,
+                        record.level,
+                        record.region,
+                        record.players,
+                        record.max_players,
+                        record.rules.rounds,
+                    ));
+                    let full = record.max_players <= record.players;
+                    if ui.add_enabled(!full, egui::Button::new("Join")).clicked() {
+                        args.iroh = record.code;
+                        commands.insert_resource(NetworkRole::Client);
+                        next_state.set(GameState::Matchmaking);
+                    }
+                });
+            }
         });
 }
 
@@ -113,9 +265,18 @@ fn on_click<E: Debug + Clone + Reflect + Event>(
     role: NetworkRole,
 ) -> impl Fn(On<E>, Commands, Res<Args>, ResMut<NextState<GameState>>) {
     move |_ev, mut commands, args, mut next_state| {
-        if matches!(role, NetworkRole::Client) && 64 != args.iroh.len() {
+        let joining = matches!(role, NetworkRole::Client | NetworkRole::Spectator);
+        if joining && check_invite(args.signalling, &args.iroh).is_err() {
+            return;
+        }
+        if matches!(role, NetworkRole::Host) && !LEVELS.contains(&args.level.as_str()) {
             return;
         }
+        // a spectator link makes joining watch the match
+        let role = match role {
+            NetworkRole::Client if is_spectator_invite(&args.iroh) => NetworkRole::Spectator,
+            role => role,
+        };
         commands.insert_resource(role);
         next_state.set(GameState::Matchmaking);
     }
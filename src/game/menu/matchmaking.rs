@@ -3,7 +3,9 @@ use bevy_egui::{EguiContexts, EguiPrimaryContextPass, egui};
 
 use crate::game::{
     GameState,
-    online::{IrohId, network_role::NetworkRole},
+    online::{
+        MatchmakingError, Room, Spectators, network_role::NetworkRole, room::SPECTATE_PREFIX,
+    },
 };
 
 pub struct MatchmakingPlugin;
@@ -17,16 +19,42 @@ impl Plugin for MatchmakingPlugin {
     }
 }
 
-fn show_text(mut context: EguiContexts, iroh_id: Option<Res<IrohId>>, role: Res<NetworkRole>) {
-    let message = if matches!(*role, NetworkRole::Host) {
-        if let Some(id) = iroh_id.map(|id| id.0.to_string()) {
-            format!(
-                "Share this ID\n{0}\nor open this\nhttps://yadokani389.github.io/online-breakout/#{0}",
-                id
-            )
-        } else {
-            "Connecting...".into()
+fn show_text(
+    mut context: EguiContexts,
+    mut next_state: ResMut<NextState<GameState>>,
+    room: Option<Res<Room>>,
+    role: Res<NetworkRole>,
+    spectators: Res<Spectators>,
+    error: Option<Res<MatchmakingError>>,
+) {
+    // the short code is looked up through the host's beacon first, the invites carry the full ticket and connect faster
+    let invite = match (&error, &room) {
+        (None, Some(room)) if matches!(*role, NetworkRole::Host) => {
+            let base = if room.web_link {
+                "https://yadokani389.github.io/online-breakout/#"
+            } else {
+                ""
+            };
+            Some((
+                room.short_code.clone(),
+                format!("{base}{}", room.code),
+                format!("{base}{SPECTATE_PREFIX}{}", room.code),
+                room.web_link,
+            ))
         }
+        _ => None,
+    };
+    let leave_text = if error.is_some() {
+        "Back to lobby"
+    } else {
+        "Cancel"
+    };
+    let message = if let Some(error) = error {
+        format!("Cannot start the match\n{}", error.0)
+    } else if invite.is_some() {
+        "Share this code or link".into()
+    } else if matches!(*role, NetworkRole::Spectator) && room.is_some() {
+        "Waiting for the match to start...".into()
     } else {
         "Connecting...".into()
     };
@@ -40,6 +68,36 @@ fn show_text(mut context: EguiContexts, iroh_id: Option<Res<IrohId>>, role: Res<
                     for line in message.lines() {
                         ui.label(egui::RichText::new(line).size(20.));
                     }
+                    // going back to the lobby closes the room
+                    if ui.button(leave_text).clicked() {
+                        next_state.set(GameState::Lobby);
+                    }
+                    if let Some((code, link, spectator_link, web_link)) = invite {
+                        ui.label(egui::RichText::new(&code).size(20.).monospace());
+                        if ui.button("Copy code").clicked() {
+                            ui.ctx().copy_text(code);
+                        }
+                        if web_link {
+                            ui.hyperlink_to("Invite link", &link);
+                            if ui.button("Copy link").clicked() {
+                                ui.ctx().copy_text(link);
+                            }
+                        }
+                        if web_link {
+                            ui.hyperlink_to("Spectator link", &spectator_link);
+                        } else {
+                            ui.label(format!("Spectators join with {spectator_link}"));
+                        }
+                        if ui.button("Copy spectator link").clicked() {
+                            ui.ctx().copy_text(spectator_link);
+                        }
+                        if !spectators.0.is_empty() {
+                            ui.label(format!("Spectators ({})", spectators.0.len()));
+                            for spectator in &spectators.0 {
+                                ui.label(egui::RichText::new(spectator.to_string()).monospace());
+                            }
+                        }
+                    }
                 },
             );
         });
@@ -3,6 +3,7 @@ use bevy_egui::EguiPlugin;
 
 pub mod lobby;
 pub mod matchmaking;
+pub mod notification;
 pub mod result;
 
 pub struct MenuPlugin;
@@ -13,6 +14,7 @@ impl Plugin for MenuPlugin {
             EguiPlugin::default(),
             lobby::LobbyPlugin,
             matchmaking::MatchmakingPlugin,
+            notification::NotificationPlugin,
             result::ResultPlugin,
         ));
     }
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use web_time::Instant;
+
+/// How long a notification stays on screen.
+const NOTIFICATION_DURATION: Duration = Duration::from_secs(4);
+
+pub struct NotificationPlugin;
+
+impl Plugin for NotificationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Notifications>()
+            .add_systems(Startup, setup_notification_area)
+            .add_systems(Update, update_notifications);
+    }
+}
+
+/// Short messages shown in the top right corner for a few seconds, in any state.
+#[derive(Resource, Default)]
+pub struct Notifications(Vec<Notification>);
+
+struct Notification {
+    text: String,
+    color: Color,
+    expires: Instant,
+}
+
+impl Notifications {
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.push(text.into(), Color::srgb(0.9, 0.9, 0.9));
+    }
+
+    pub fn warn(&mut self, text: impl Into<String>) {
+        self.push(text.into(), Color::srgb(0.9, 0.7, 0.3));
+    }
+
+    fn push(&mut self, text: String, color: Color) {
+        self.0.push(Notification {
+            text,
+            color,
+            expires: Instant::now() + NOTIFICATION_DURATION,
+        });
+    }
+}
+
+#[derive(Component)]
+struct NotificationArea;
+
+fn setup_notification_area(mut commands: Commands) {
+    commands.spawn((
+        NotificationArea,
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            right: Val::Px(10.0),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::End,
+            row_gap: Val::Px(4.0),
+            ..default()
+        },
+        ZIndex(3000),
+    ));
+}
+
+fn update_notifications(
+    mut commands: Commands,
+    mut notifications: ResMut<Notifications>,
+    area: Single<Entity, With<NotificationArea>>,
+) {
+    let now = Instant::now();
+    if notifications
+        .0
+        .iter()
+        .any(|notification| notification.expires <= now)
+    {
+        notifications
+            .0
+            .retain(|notification| now < notification.expires);
+    }
+    if !notifications.is_changed() {
+        return;
+    }
+
+    commands.entity(*area).despawn_related::<Children>();
+    for notification in &notifications.0 {
+        commands.spawn((
+            ChildOf(*area),
+            Node {
+                padding: UiRect::axes(Val::Px(10.0), Val::Px(4.0)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.7)),
+            children![(
+                Text::new(notification.text.clone()),
+                TextFont::from_font_size(18.0),
+                TextColor(notification.color),
+            )],
+        ));
+    }
+}
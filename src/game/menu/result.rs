@@ -1,7 +1,14 @@
 use bevy::prelude::*;
 use bevy_ggrs::LocalPlayers;
 
-use crate::game::{GameState, components::Team, timer::GameResult};
+use crate::game::{
+    GameState,
+    components::Team,
+    online::connection::Desync,
+    round::RoundResult,
+    rules::{MatchRules, TeamMode, VictoryCondition},
+    timer::GameResult,
+};
 
 pub struct ResultPlugin;
 
@@ -14,63 +21,56 @@ impl Plugin for ResultPlugin {
 fn setup_result_screen(
     mut commands: Commands,
     game_result: Option<Res<GameResult>>,
+    desync: Option<Res<Desync>>,
     local_players: Res<LocalPlayers>,
+    rules: Res<MatchRules>,
 ) {
+    if let Some(desync) = desync {
+        setup_desync_screen(&mut commands, &desync);
+        return;
+    }
     let Some(result) = game_result else {
         return;
     };
 
     // Get local player's team
-    let local_team = local_players.0.first().copied().unwrap_or(0);
+    let local_team = Team(local_players.0.first().copied().unwrap_or(0));
+    let local_alliance = rules.alliance(local_team);
 
     // Victory message
-    let (winner_text, winner_color, score_text) = match result.winner {
-        Some(Team(0)) => {
-            let personal_message = if local_team == 0 {
-                "You Win!"
-            } else {
-                "You Lose!"
-            };
-            (
-                personal_message,
-                if local_team == 0 {
-                    Color::hsl(Team(0).hue(), 0.8, 0.7)
-                } else {
-                    Color::srgb(0.8, 0.3, 0.3)
-                },
-                format!(
-                    "Player 1: {} blocks vs Player 2: {} blocks",
-                    result.team0_blocks, result.team1_blocks
-                ),
-            )
-        }
-        Some(Team(1)) => {
-            let personal_message = if local_team == 1 {
-                "You Win!"
-            } else {
-                "You Lose!"
-            };
-            (
-                personal_message,
-                if local_team == 1 {
-                    Color::hsl(Team(1).hue(), 0.8, 0.7)
-                } else {
-                    Color::srgb(0.8, 0.3, 0.3)
-                },
-                format!(
-                    "Player 2: {} blocks vs Player 1: {} blocks",
-                    result.team1_blocks, result.team0_blocks
-                ),
-            )
-        }
-        None => (
-            "It's a Draw!",
-            Color::srgb(0.7, 0.7, 0.7),
-            format!("Both players: {} blocks", result.team0_blocks),
+    let (winner_text, winner_color): (String, _) = match result.winner {
+        // spectators have no local player to cheer for
+        Some(winner) if local_players.0.is_empty() => (
+            format!("{} Wins!", alliance_name(&rules, winner)),
+            Color::srgb(0.9, 0.9, 0.9),
+        ),
+        Some(winner) if winner == local_alliance && !result.forfeited.is_empty() => (
+            "You Win by Forfeit!".into(),
+            Color::hsl(local_team.hue(), 0.8, 0.7),
         ),
-        Some(_) => ("Game Over", Color::WHITE, "".to_string()),
+        Some(winner) if winner == local_alliance => {
+            ("You Win!".into(), Color::hsl(local_team.hue(), 0.8, 0.7))
+        }
+        Some(_) => ("You Lose!".into(), Color::srgb(0.8, 0.3, 0.3)),
+        None => ("It's a Draw!".into(), Color::srgb(0.7, 0.7, 0.7)),
     };
 
+    let mut score_text = match result.rounds.as_slice() {
+        [round] => round_summary(round, &rules),
+        rounds => rounds
+            .iter()
+            .enumerate()
+            .map(|(index, round)| format!("Round {}: {}", index + 1, round_summary(round, &rules)))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    for player in &result.forfeited {
+        if !score_text.is_empty() {
+            score_text.push('\n');
+        }
+        score_text.push_str(&format!("Player {} left the match", player + 1));
+    }
+
     // Main UI container
     commands.spawn((
         DespawnOnExit(GameState::GameOver),
@@ -116,3 +116,98 @@ fn setup_result_screen(
         ],
     ));
 }
+
+/// Fatal screen for simulations that diverged, with what is needed to track the desync down.
+fn setup_desync_screen(commands: &mut Commands, desync: &Desync) {
+    let details = format!(
+        "Frame {}\nLocal checksum: {:032x}\nChecksum of {}: {:032x}",
+        desync.frame, desync.local_checksum, desync.peer, desync.remote_checksum
+    );
+    commands.spawn((
+        DespawnOnExit(GameState::GameOver),
+        Node {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.2, 0.0, 0.0, 0.9)),
+        children![
+            (
+                Text::new("DESYNC"),
+                TextFont::from_font_size(48.0),
+                TextColor(Color::srgb(0.9, 0.3, 0.3)),
+                Node {
+                    margin: UiRect::bottom(Val::Px(30.0)),
+                    ..default()
+                },
+            ),
+            (
+                Text::new("The game states of the players diverged, the match cannot continue."),
+                TextFont::from_font_size(24.0),
+                TextColor(Color::WHITE),
+                Node {
+                    margin: UiRect::bottom(Val::Px(20.0)),
+                    ..default()
+                },
+            ),
+            (
+                Text::new(details),
+                TextFont::from_font_size(20.0),
+                TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                TextLayout::new_with_justify(Justify::Center),
+            ),
+        ],
+    ));
+}
+
+fn round_summary(round: &RoundResult, rules: &MatchRules) -> String {
+    let mut summary = match (rules.victory, rules.team_mode) {
+        (VictoryCondition::MostCaptures, _) => round
+            .captures
+            .iter()
+            .enumerate()
+            .map(|(alliance, captures)| {
+                format!("{}: {captures} captures", alliance_name(rules, alliance))
+            })
+            .collect::<Vec<_>>()
+            .join(" vs "),
+        (_, TeamMode::FreeForAll) => round
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(player, blocks)| format!("Player {}: {blocks} blocks", player + 1))
+            .collect::<Vec<_>>()
+            .join(" vs "),
+        (_, TeamMode::TwoVsTwo) => round
+            .scores
+            .iter()
+            .enumerate()
+            .map(|(alliance, score)| {
+                let members = round
+                    .blocks
+                    .iter()
+                    .enumerate()
+                    .filter(|(player, _)| rules.alliance(Team(*player)) == alliance)
+                    .map(|(player, blocks)| format!("P{} {blocks}", player + 1))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("Team {}: {score} blocks ({members})", alliance + 1)
+            })
+            .collect::<Vec<_>>()
+            .join(" vs "),
+    };
+    if round.overtime {
+        summary.push_str(" (sudden death)");
+    }
+    summary
+}
+
+fn alliance_name(rules: &MatchRules, alliance: usize) -> String {
+    match rules.team_mode {
+        TeamMode::FreeForAll => format!("Player {}", alliance + 1),
+        TeamMode::TwoVsTwo => format!("Team {}", alliance + 1),
+    }
+}
@@ -1,17 +1,26 @@
 use bevy::{camera::ScalingMode, prelude::*};
 use bevy_ggrs::prelude::*;
-use components::Team;
+use components::{Position, Team};
+use fixed::Fixed;
 use matchbox_socket::PeerId;
 
 mod ball;
 mod components;
 mod field;
+mod fixed;
 mod item;
 mod menu;
 mod online;
 mod paddle;
+mod rng;
+mod round;
+mod rules;
 mod timer;
 
+pub use online::{
+    IrohSocket, Room, connection::RECONNECT_GRACE, loopback_signaller, network_role::NetworkRole,
+};
+
 type Config = bevy_ggrs::GgrsConfig<u8, PeerId>;
 
 pub struct GamePlugin;
@@ -27,21 +36,27 @@ impl Plugin for GamePlugin {
             online::OnlinePlugin,
             item::ItemPlugin,
             timer::TimerPlugin,
+            round::RoundPlugin,
         ))
         .init_state::<GameState>()
+        .init_resource::<rules::MatchRules>()
         .add_systems(Startup, setup_graphics)
         .add_systems(
             GgrsSchedule,
             despawn_out_of_bounds_entities.after(field::toggle_cell),
         )
-        .rollback_component_with_clone::<Transform>()
-        .rollback_component_with_copy::<Team>();
+        .add_systems(Update, sync_transforms)
+        .rollback_component_with_copy::<Position>()
+        .rollback_component_with_copy::<Team>()
+        .rollback_resource_with_clone::<rng::MatchRng>()
+        .checksum_component_with_hash::<Position>()
+        .checksum_resource_with_hash::<rng::MatchRng>();
     }
 }
 
 #[derive(States, Clone, Eq, PartialEq, Debug, Hash, Default)]
 #[states(scoped_entities)]
-enum GameState {
+pub enum GameState {
     #[default]
     Lobby,
     Matchmaking,
@@ -61,10 +76,18 @@ fn setup_graphics(mut commands: Commands) {
     ));
 }
 
-fn despawn_out_of_bounds_entities(mut commands: Commands, query: Query<(Entity, &Transform)>) {
-    for (entity, transform) in query {
-        if 1200. < transform.translation.x.abs() || 2000. < transform.translation.y.abs() {
+fn despawn_out_of_bounds_entities(mut commands: Commands, query: Query<(Entity, &Position)>) {
+    for (entity, position) in query {
+        if Fixed::from_int(1200) < position.x.abs() || Fixed::from_int(2000) < position.y.abs() {
             commands.entity(entity).despawn();
         }
     }
 }
+
+fn sync_transforms(query: Query<(&Position, &mut Transform), Changed<Position>>) {
+    for (position, mut transform) in query {
+        let translation = position.to_vec2();
+        transform.translation.x = translation.x;
+        transform.translation.y = translation.y;
+    }
+}
@@ -0,0 +1,301 @@
+use std::{collections::HashMap, time::Duration};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_ggrs::{
+    Session,
+    ggrs::{GgrsEvent, P2PSession},
+};
+use matchbox_socket::{PeerId, PeerState};
+use web_time::Instant;
+
+use super::{IrohSocket, Room, Signalling, loopback_signaller, p2p_mode};
+use crate::{
+    args::{Args, SignallingBackend},
+    game::{
+        Config, GameState, components::Team, menu::notification::Notifications,
+        round::MatchProgress, rules::MatchRules, timer::GameResult,
+    },
+};
+
+pub struct ConnectionPlugin;
+
+impl Plugin for ConnectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(GameState::InGame),
+            (start_connection_tracking, setup_reconnect_overlay).run_if(p2p_mode),
+        )
+        .add_systems(
+            Update,
+            (
+                reconnect_peers
+                    .run_if(resource_exists::<IrohSocket>)
+                    .run_if(resource_exists::<Room>),
+                handle_session_events.run_if(resource_exists::<Session<Config>>),
+                update_reconnect_overlay,
+            )
+                .chain()
+                .run_if(in_state(GameState::InGame))
+                .run_if(resource_exists::<ConnectionStatus>),
+        )
+        .add_systems(OnExit(GameState::InGame), resume_time);
+    }
+}
+
+/// How long a dropped peer has to come back before it forfeits the match.
+pub const RECONNECT_GRACE: Duration = Duration::from_secs(20);
+
+/// Frames per second of the rollback schedule, the bevy_ggrs default.
+pub const FRAME_RATE: f64 = 60.0;
+
+/// Peers whose connection dropped during the match.
+#[derive(Resource, Default)]
+pub struct ConnectionStatus {
+    /// Interrupted peers and when they will be dropped for good.
+    interrupted: HashMap<PeerId, Instant>,
+    /// Players who did not come back in time.
+    forfeited: Vec<usize>,
+    /// End of a pause GGRS recommended to let the other peers catch up.
+    waiting_until: Option<Instant>,
+}
+
+/// Checksums of a frame the peers disagree on, ending the match.
+#[derive(Resource)]
+pub struct Desync {
+    pub frame: i32,
+    pub local_checksum: u128,
+    pub remote_checksum: u128,
+    /// Player whose checksum differs from ours.
+    pub peer: String,
+}
+
+fn start_connection_tracking(mut commands: Commands) {
+    commands.insert_resource(ConnectionStatus::default());
+}
+
+/// Asks the signaller to negotiate a new WebRTC connection with peers whose channel closed.
+///
+/// The matchbox server cannot do that, so a dropped peer there only comes back on its own.
+fn reconnect_peers(
+    mut socket: ResMut<IrohSocket>,
+    signalling: Option<Res<Signalling>>,
+    room: Res<Room>,
+    args: Res<Args>,
+) {
+    let id = socket.id();
+    for (peer, state) in socket.update_peers() {
+        match state {
+            PeerState::Connected => info!("Peer {peer} connected again"),
+            PeerState::Disconnected => {
+                warn!("Connection to peer {peer} dropped, trying to reconnect");
+                if let Some(signalling) = &signalling {
+                    signalling.0.reconnect(peer);
+                } else if let Some(id) = id
+                    && args.signalling == SignallingBackend::Loopback
+                {
+                    loopback_signaller::reconnect(&room.code, id, peer);
+                }
+            }
+        }
+    }
+}
+
+/// The rules and rounds a result by forfeit is built from.
+#[derive(SystemParam)]
+struct MatchRecord<'w> {
+    progress: Res<'w, MatchProgress>,
+    rules: Res<'w, MatchRules>,
+}
+
+/// Drains the events of the P2P session, logs them and reacts to them.
+///
+/// Interrupted peers pause the match, which GGRS does by itself once it runs out of predicted frames,
+/// so the pause only needs to be shown. Peers gone for good end the match by forfeit, and a desync ends it for everyone.
+fn handle_session_events(
+    mut commands: Commands,
+    mut session: ResMut<Session<Config>>,
+    mut status: ResMut<ConnectionStatus>,
+    mut notifications: ResMut<Notifications>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    record: MatchRecord,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let Session::P2P(p2p) = session.as_mut() else {
+        return;
+    };
+    for event in p2p.events().collect::<Vec<_>>() {
+        match event {
+            GgrsEvent::Synchronizing { addr, total, count } => {
+                debug!(peer = %addr, count, total, "Synchronizing");
+            }
+            GgrsEvent::Synchronized { addr } => {
+                info!(peer = %addr, "Synchronized");
+                notifications.info(format!("{} is ready", peer_name(p2p, addr)));
+            }
+            GgrsEvent::NetworkInterrupted {
+                addr,
+                disconnect_timeout,
+            } => {
+                warn!(peer = %addr, disconnect_timeout, "Network interrupted");
+                notifications.warn(format!("Lost connection to {}", peer_name(p2p, addr)));
+                // the match does not wait for spectators
+                if !is_spectator(p2p, addr) {
+                    let timeout = Duration::from_millis(disconnect_timeout as u64);
+                    status.interrupted.insert(addr, Instant::now() + timeout);
+                }
+            }
+            GgrsEvent::NetworkResumed { addr } => {
+                info!(peer = %addr, "Network resumed");
+                notifications.info(format!("{} reconnected", peer_name(p2p, addr)));
+                status.interrupted.remove(&addr);
+            }
+            GgrsEvent::Disconnected { addr } => {
+                warn!(peer = %addr, "Disconnected");
+                notifications.warn(format!("{} left the match", peer_name(p2p, addr)));
+                status.interrupted.remove(&addr);
+                // spectators leaving do not affect the match
+                if !is_spectator(p2p, addr) {
+                    status.forfeited.extend(p2p.handles_by_address(addr));
+                }
+            }
+            GgrsEvent::WaitRecommendation { skip_frames } => {
+                info!(skip_frames, "Wait recommended");
+                // we are ahead of the others, hold back for the recommended frames to let them catch up
+                let wait = Duration::from_secs_f64(skip_frames as f64 / FRAME_RATE);
+                status.waiting_until = Some(Instant::now() + wait);
+                virtual_time.pause();
+            }
+            GgrsEvent::DesyncDetected {
+                frame,
+                local_checksum,
+                remote_checksum,
+                addr,
+            } => {
+                error!(
+                    peer = %addr,
+                    frame,
+                    local_checksum = %format!("{local_checksum:032x}"),
+                    remote_checksum = %format!("{remote_checksum:032x}"),
+                    "Desync detected"
+                );
+                commands.insert_resource(Desync {
+                    frame,
+                    local_checksum,
+                    remote_checksum,
+                    peer: peer_name(p2p, addr),
+                });
+                // the simulations diverged, nothing sensible can come out of continuing
+                commands.remove_resource::<Session<Config>>();
+                next_state.set(GameState::GameOver);
+                return;
+            }
+        }
+    }
+
+    if status
+        .waiting_until
+        .is_some_and(|until| until <= Instant::now())
+    {
+        status.waiting_until = None;
+        virtual_time.unpause();
+    }
+
+    if status.forfeited.is_empty() {
+        return;
+    }
+
+    // the players still connected win if they are all on one side
+    let mut remaining = (0..p2p.num_players())
+        .filter(|player| !status.forfeited.contains(player))
+        .map(|player| record.rules.alliance(Team(player)))
+        .collect::<Vec<_>>();
+    remaining.sort_unstable();
+    remaining.dedup();
+    let winner = match remaining.as_slice() {
+        [alliance] => Some(*alliance),
+        _ => None,
+    };
+    info!(
+        "Match ended by forfeit of {:?}, winner: {winner:?}",
+        status.forfeited
+    );
+
+    commands.insert_resource(GameResult {
+        winner,
+        rounds: record.progress.results.clone(),
+        forfeited: status.forfeited.clone(),
+    });
+    next_state.set(GameState::GameOver);
+}
+
+/// Display name of the players behind a peer.
+fn peer_name(session: &P2PSession<Config>, addr: PeerId) -> String {
+    if is_spectator(session, addr) {
+        return "A spectator".into();
+    }
+    match session.handles_by_address(addr).as_slice() {
+        [] => format!("Peer {addr}"),
+        handles => handles
+            .iter()
+            .map(|handle| format!("Player {}", handle + 1))
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+/// Spectators get the handles after the players.
+fn is_spectator(session: &P2PSession<Config>, addr: PeerId) -> bool {
+    session
+        .handles_by_address(addr)
+        .iter()
+        .any(|handle| session.num_players() <= *handle)
+}
+
+fn resume_time(mut virtual_time: ResMut<Time<Virtual>>) {
+    virtual_time.unpause();
+}
+
+#[derive(Component)]
+struct ReconnectOverlay;
+
+#[derive(Component)]
+struct ReconnectText;
+
+fn setup_reconnect_overlay(mut commands: Commands) {
+    commands.spawn((
+        ReconnectOverlay,
+        DespawnOnExit(GameState::InGame),
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+        ZIndex(2000),
+        Visibility::Hidden,
+        children![(
+            ReconnectText,
+            Text::default(),
+            TextFont::from_font_size(32.0),
+            TextColor(Color::WHITE),
+            TextLayout::new_with_justify(Justify::Center),
+        )],
+    ));
+}
+
+fn update_reconnect_overlay(
+    status: Res<ConnectionStatus>,
+    mut overlay: Single<&mut Visibility, With<ReconnectOverlay>>,
+    mut text: Single<&mut Text, With<ReconnectText>>,
+) {
+    let Some(deadline) = status.interrupted.values().min() else {
+        overlay.set_if_neq(Visibility::Hidden);
+        return;
+    };
+    overlay.set_if_neq(Visibility::Inherited);
+    let remaining = deadline.saturating_duration_since(Instant::now()).as_secs();
+    text.0 = format!("Waiting for opponent to reconnect...\nForfeit in {remaining}s");
+}
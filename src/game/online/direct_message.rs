@@ -1,46 +1,264 @@
 // https://github.com/johanhelsing/matchbox/blob/9e18318c46d12609a41fd56cf3fb9bb47cea6dab/examples/custom_signaller/src/direct_message.rs
 
-use iroh::{Endpoint, PublicKey, endpoint::Connection, protocol::ProtocolHandler};
-use matchbox_socket::PeerEvent;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-pub const DIRECT_MESSAGE_ALPN: &[u8] = b"/matchbox-direct-message/0";
+use bevy::prelude::*;
+use iroh::{
+    Endpoint, PublicKey,
+    endpoint::{Connection, ReadExactError, RecvStream, SendStream},
+    protocol::ProtocolHandler,
+};
+use matchbox_socket::{PeerEvent, PeerId, PeerSignal};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+pub const DIRECT_MESSAGE_ALPN: &[u8] = b"/matchbox-direct-message/1";
+
+/// Frames larger than this are refused, SDP offers stay far below it.
+const MAX_FRAME_LEN: usize = 1024 * 1024;
+
+/// Signals queued per peer, a stream that falls this far behind is given up.
+const QUEUE_LEN: usize = 64;
+
+/// A signal as it goes over the wire, the receiving node knows who it is from.
+#[derive(Serialize, Deserialize, Debug)]
+struct SignalFrame {
+    sender: PeerId,
+    data: PeerSignal,
+}
+
+/// Signals to and from other nodes over one long-lived connection per peer.
+///
+/// Each connection carries a bidirectional stream of length-prefixed postcard frames,
+/// so signals flow both ways without a handshake per signal.
 #[derive(Debug, Clone)]
-pub struct DirectMessageProtocol(pub async_broadcast::Sender<(PublicKey, PeerEvent)>);
+pub struct DirectMessages {
+    endpoint: Endpoint,
+    /// Where received signals go, with the node they came from.
+    inbox: async_broadcast::Sender<(PublicKey, PeerEvent)>,
+    /// Nodes whose stream ended before all signals queued for it were sent.
+    failures: async_broadcast::Sender<PublicKey>,
+    /// Queues of the streams to each peer.
+    outgoing: Arc<Mutex<HashMap<PublicKey, mpsc::Sender<SignalFrame>>>>,
+}
+
+impl DirectMessages {
+    pub fn new(
+        endpoint: Endpoint,
+        inbox: async_broadcast::Sender<(PublicKey, PeerEvent)>,
+        failures: async_broadcast::Sender<PublicKey>,
+    ) -> Self {
+        Self {
+            endpoint,
+            inbox,
+            failures,
+            outgoing: Default::default(),
+        }
+    }
+
+    /// Queues a signal for `target`, connecting first if there is no stream to it yet.
+    ///
+    /// Never waits, so one slow peer cannot hold up the signals to the others.
+    /// A signal that cannot be queued, or a stream that drops queued signals later,
+    /// reports its node through the failures channel, so the negotiation can start over.
+    pub fn send(&self, target: PublicKey, sender: PeerId, data: PeerSignal) -> anyhow::Result<()> {
+        let queue = self.queue(target);
+        let result = match queue.try_send(SignalFrame { sender, data }) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Full(_)) => {
+                // the stream is stuck, the next signal gets a new one
+                let mut outgoing = self.outgoing.lock().unwrap();
+                if outgoing
+                    .get(&target)
+                    .is_some_and(|current| current.same_channel(&queue))
+                {
+                    outgoing.remove(&target);
+                }
+                Err(anyhow::anyhow!("Signal queue to {target} is full"))
+            }
+            Err(TrySendError::Closed(_)) => {
+                Err(anyhow::anyhow!("Signal stream to {target} closed"))
+            }
+        };
+        self.report_failure(target);
+        result
+    }
+
+    fn queue(&self, target: PublicKey) -> mpsc::Sender<SignalFrame> {
+        let mut outgoing = self.outgoing.lock().unwrap();
+        if let Some(queue) = outgoing.get(&target).filter(|queue| !queue.is_closed()) {
+            return queue.clone();
+        }
+        let (send, recv) = mpsc::channel(QUEUE_LEN);
+        outgoing.insert(target, send.clone());
+        let this = self.clone();
+        n0_future::task::spawn(async move {
+            let result = this.clone().dial(target, recv).await;
+            this.end_stream(target, result);
+        });
+        send
+    }
+
+    async fn dial(
+        self,
+        target: PublicKey,
+        queue: mpsc::Receiver<SignalFrame>,
+    ) -> anyhow::Result<()> {
+        let connection = self.endpoint.connect(target, DIRECT_MESSAGE_ALPN).await?;
+        let (send, recv) = connection.open_bi().await?;
+        let this = self.clone();
+        n0_future::task::spawn(async move {
+            if let Err(e) = this.read_frames(target, recv).await {
+                error!("Signal stream from {target} failed: {e:#}");
+            }
+        });
+        write_frames(&connection, send, queue).await
+    }
+
+    /// Drops the queue of a finished stream, so the next signal to `target` connects again,
+    /// and reports the node if signals were lost.
+    fn end_stream(&self, target: PublicKey, result: anyhow::Result<()>) {
+        {
+            let mut outgoing = self.outgoing.lock().unwrap();
+            // a newer stream to the peer may have taken the place of this one already
+            if outgoing.get(&target).is_some_and(|queue| queue.is_closed()) {
+                outgoing.remove(&target);
+            }
+        }
+        if let Err(e) = result {
+            error!("Signal stream to {target} failed: {e:#}");
+            self.report_failure(target);
+        }
+    }
+
+    fn report_failure(&self, target: PublicKey) {
+        if let Err(e) = self.failures.try_broadcast(target) {
+            warn!("Failed to report the signal stream to {target}: {e}");
+        }
+    }
 
-impl DirectMessageProtocol {
     async fn handle_connection(self, connection: Connection) -> anyhow::Result<()> {
-        let _remote_node_id = connection.remote_node_id()?;
-        let mut recv = connection.accept_uni().await?;
-        let data = recv.read_to_end(1024 * 63).await?;
-        connection.close(0u8.into(), b"done");
-        let data: PeerEvent = serde_json::from_slice(&data)?;
-        let data = match &data {
-            PeerEvent::Signal { .. } => (_remote_node_id, data),
-            _ => {
-                anyhow::bail!("unsupported event type: {:?}", data)
+        let remote = connection.remote_node_id()?;
+        let (send, recv) = connection.accept_bi().await?;
+        // answer over the same connection, unless we already have a stream to the peer
+        let answer = {
+            let mut outgoing = self.outgoing.lock().unwrap();
+            match outgoing.get(&remote) {
+                Some(queue) if !queue.is_closed() => None,
+                _ => {
+                    let (queue_send, queue_recv) = mpsc::channel(QUEUE_LEN);
+                    outgoing.insert(remote, queue_send);
+                    Some(queue_recv)
+                }
             }
         };
-        self.0.broadcast(data).await?;
+        if let Some(queue) = answer {
+            let this = self.clone();
+            let connection = connection.clone();
+            n0_future::task::spawn(async move {
+                let result = write_frames(&connection, send, queue).await;
+                this.end_stream(remote, result);
+            });
+        }
+        self.read_frames(remote, recv).await
+    }
+
+    async fn read_frames(&self, remote: PublicKey, mut recv: RecvStream) -> anyhow::Result<()> {
+        while let Some(frame) = read_frame(&mut recv).await? {
+            let event = PeerEvent::Signal {
+                sender: frame.sender,
+                data: frame.data,
+            };
+            self.inbox.broadcast((remote, event)).await?;
+        }
         Ok(())
     }
 }
 
-impl ProtocolHandler for DirectMessageProtocol {
+impl ProtocolHandler for DirectMessages {
     fn accept(&self, connection: Connection) -> n0_future::boxed::BoxFuture<anyhow::Result<()>> {
         Box::pin(self.clone().handle_connection(connection))
     }
 }
-pub async fn send_direct_message(
-    endpoint: &Endpoint,
-    iroh_target: PublicKey,
-    payload: PeerEvent,
+
+/// Sends the queued signals until the queue is dropped or the connection closes.
+///
+/// A connection closing with nothing queued is no failure, the next signal simply connects again.
+async fn write_frames(
+    connection: &Connection,
+    mut send: SendStream,
+    mut queue: mpsc::Receiver<SignalFrame>,
 ) -> anyhow::Result<()> {
-    let connection = endpoint.connect(iroh_target, DIRECT_MESSAGE_ALPN).await?;
-    let payload = serde_json::to_vec(&payload)?;
-    let mut send_stream = connection.open_uni().await?;
-    send_stream.write_all(&payload).await?;
-    send_stream.finish()?;
-    connection.closed().await;
-    // connection.close(0u8.into(), b"done");
+    loop {
+        let frame = tokio::select! {
+            frame = queue.recv() => frame,
+            reason = connection.closed() => {
+                queue.close();
+                anyhow::ensure!(queue.is_empty(), "Connection closed with signals queued: {reason}");
+                return Ok(());
+            }
+        };
+        let Some(frame) = frame else {
+            break;
+        };
+        let bytes = postcard::to_stdvec(&frame)?;
+        send.write_all(&(bytes.len() as u32).to_le_bytes()).await?;
+        send.write_all(&bytes).await?;
+    }
+    send.finish()?;
     Ok(())
 }
+
+/// Next frame of the stream, or `None` once the peer finished it.
+async fn read_frame(recv: &mut RecvStream) -> anyhow::Result<Option<SignalFrame>> {
+    let mut len = [0; 4];
+    match recv.read_exact(&mut len).await {
+        Ok(()) => {}
+        Err(ReadExactError::FinishedEarly(0)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    anyhow::ensure!(
+        len <= MAX_FRAME_LEN,
+        "Signal frame of {len} bytes is too large"
+    );
+    let mut bytes = vec![0; len];
+    recv.read_exact(&mut bytes).await?;
+    Ok(Some(postcard::from_bytes(&bytes)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::{RelayMode, SecretKey};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn full_queue_reports_failure() {
+        let endpoint = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap();
+        let (inbox, _inbox) = async_broadcast::broadcast(1);
+        let (failures, mut failed) = async_broadcast::broadcast(1);
+        let messages = DirectMessages::new(endpoint, inbox, failures);
+        let target = SecretKey::from_bytes(&[1; 32]).public();
+        // a stream that never catches up
+        let (queue, _stalled) = mpsc::channel(QUEUE_LEN);
+        messages.outgoing.lock().unwrap().insert(target, queue);
+
+        let sender = PeerId(uuid::Uuid::nil());
+        let signal = || PeerSignal::IceCandidate(String::new());
+        for _ in 0..QUEUE_LEN {
+            messages.send(target, sender, signal()).unwrap();
+        }
+        assert!(failed.try_recv().is_err());
+        assert!(messages.send(target, sender, signal()).is_err());
+        assert_eq!(failed.try_recv().ok(), Some(target));
+        assert!(!messages.outgoing.lock().unwrap().contains_key(&target));
+    }
+}
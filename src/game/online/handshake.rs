@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever the messages between peers change.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// What a peer announces about its build, peers only match if these are equal.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Handshake {
+    pub protocol: u32,
+    /// Package version, only used to tell the players which build is which.
+    pub version: String,
+    /// Hash of the game sources, which covers physics constants, levels and the rule set.
+    ///
+    /// The rules of a match are no part of the build: the host picks them in the lobby
+    /// and sends them with its offer, so there is nothing to compare before that.
+    pub content_hash: String,
+}
+
+impl Handshake {
+    pub fn local() -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            version: env!("CARGO_PKG_VERSION").into(),
+            content_hash: env!("GAME_CONTENT_HASH").into(),
+        }
+    }
+
+    /// Refuses a peer running another build, with a message for the player.
+    pub fn check(&self, remote: Option<&Handshake>) -> Result<(), String> {
+        let Some(remote) = remote else {
+            return Err(format!(
+                "Version mismatch: the other player runs an older build than yours ({})",
+                self.version
+            ));
+        };
+        if self == remote {
+            return Ok(());
+        }
+        let difference = if self.protocol != remote.protocol {
+            format!("protocol {} vs {}", self.protocol, remote.protocol)
+        } else {
+            "the game content differs".into()
+        };
+        Err(format!(
+            "Version mismatch: you run {} ({}), the other player runs {} ({}); {difference}",
+            self.version, self.content_hash, remote.version, remote.content_hash
+        ))
+    }
+}
@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use data_encoding::HEXLOWER;
+use iroh::SecretKey;
+
+/// Secret key of the identity stored by an earlier launch, if there is a usable one.
+pub fn load() -> Option<SecretKey> {
+    let stored = match storage::read() {
+        Ok(stored) => stored?,
+        Err(e) => {
+            warn!("Failed to read the stored identity: {e:#}");
+            return None;
+        }
+    };
+    let bytes = HEXLOWER
+        .decode(stored.trim().as_bytes())
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok());
+    if bytes.is_none() {
+        warn!("Ignoring a corrupted stored identity");
+    }
+    bytes.map(|bytes| SecretKey::from_bytes(&bytes))
+}
+
+/// Keeps the identity for the next launches.
+pub fn store(secret_key: &SecretKey) {
+    if let Err(e) = storage::write(&HEXLOWER.encode(&secret_key.to_bytes())) {
+        warn!("Failed to store the identity, the next launch gets a new one: {e:#}");
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod storage {
+    use std::{
+        fs::{self, File, OpenOptions},
+        io::{ErrorKind, Write},
+        path::PathBuf,
+    };
+
+    use anyhow::Context;
+
+    fn path() -> anyhow::Result<PathBuf> {
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+            .context("No configuration directory found")?;
+        Ok(config.join("online-breakout").join("secret.key"))
+    }
+
+    pub fn read() -> anyhow::Result<Option<String>> {
+        let path = path()?;
+        let stored = match fs::read_to_string(&path) {
+            Ok(stored) => stored,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        // files written by older builds could be read by every user
+        restrict(&File::open(&path)?)?;
+        Ok(Some(stored))
+    }
+
+    pub fn write(stored: &str) -> anyhow::Result<()> {
+        let path = path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        // the mode only applies to new files
+        restrict(&file)?;
+        file.write_all(stored.as_bytes())?;
+        Ok(())
+    }
+
+    /// Makes the key readable by the owner only.
+    #[cfg(unix)]
+    fn restrict(file: &File) -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        if file.metadata()?.permissions().mode() & 0o077 != 0 {
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn restrict(_file: &File) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod storage {
+    use anyhow::Context;
+
+    const KEY: &str = "online-breakout-secret-key";
+
+    fn local_storage() -> anyhow::Result<web_sys::Storage> {
+        web_sys::window()
+            .context("No window")?
+            .local_storage()
+            .ok()
+            .flatten()
+            .context("Browser storage is not available")
+    }
+
+    pub fn read() -> anyhow::Result<Option<String>> {
+        local_storage()?
+            .get_item(KEY)
+            .ok()
+            .context("Failed to read browser storage")
+    }
+
+    pub fn write(stored: &str) -> anyhow::Result<()> {
+        local_storage()?
+            .set_item(KEY, stored)
+            .ok()
+            .context("Failed to write browser storage")
+    }
+}
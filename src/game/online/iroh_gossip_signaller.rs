@@ -1,14 +1,19 @@
 // https://github.com/johanhelsing/matchbox/blob/9e18318c46d12609a41fd56cf3fb9bb47cea6dab/examples/custom_signaller/src/iroh_gossip_signaller.rs
 
-use std::{collections::BTreeMap, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use anyhow::Context;
 use bevy::prelude::*;
 use futures::FutureExt;
-use iroh::{Endpoint, PublicKey, protocol::Router};
-use iroh_gossip::{
-    net::{Event, GOSSIP_ALPN, Gossip, GossipEvent, GossipReceiver, GossipSender, Message},
-    proto::TopicId,
+use iroh::{
+    Endpoint, NodeAddr, PublicKey, RelayMode, RelayUrl, endpoint::ConnectionType, protocol::Router,
+};
+use iroh_gossip::net::{
+    Event, GOSSIP_ALPN, Gossip, GossipEvent, GossipReceiver, GossipSender, Message,
 };
 use matchbox_socket::{
     PeerEvent, PeerId, PeerRequest, PeerSignal, SignalingError, Signaller, SignallerBuilder,
@@ -18,28 +23,55 @@ use n0_future::StreamExt;
 use serde::{Deserialize, Serialize};
 use web_time::Instant;
 
-use super::direct_message::{DIRECT_MESSAGE_ALPN, DirectMessageProtocol, send_direct_message};
+use super::{
+    direct_message::{DIRECT_MESSAGE_ALPN, DirectMessages},
+    handshake::Handshake,
+    identity,
+    room::{RoomTicket, ShortCode},
+    room_browser::{self, RoomRecord},
+    room_code,
+};
+use crate::args::{Args, RelayChoice};
 
-const GOSSIP_TOPIC_ID: TopicId = TopicId::from_bytes(*b"__online_breakout_iroh_gossip___");
+/// How often nodes announce themselves on a gossip topic.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+/// Announcements older than this are considered gone.
+pub const STALE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(15);
 
 #[derive(Debug, Clone)]
 pub struct IrohGossipSignallerBuilder {
-    _router: Router,
+    router: Router,
     gossip: Gossip,
     endpoint: Endpoint,
+    direct_messages: DirectMessages,
     matchbox_id: PeerId,
     pub iroh_id: PublicKey,
     direct_message_recv: async_broadcast::InactiveReceiver<(PublicKey, PeerEvent)>,
+    reconnect_send: async_broadcast::Sender<PeerId>,
+    reconnect_recv: async_broadcast::InactiveReceiver<PeerId>,
+    failure_recv: async_broadcast::InactiveReceiver<PublicKey>,
+    /// Why the last incompatible peer was refused.
+    refusal: Arc<Mutex<Option<String>>>,
+    /// Iroh node of each peer in the room, to tell how it is reached.
+    nodes: Arc<Mutex<BTreeMap<PeerId, PublicKey>>>,
 }
 
 impl IrohGossipSignallerBuilder {
-    pub async fn new() -> anyhow::Result<Self> {
-        info!("Creating new IrohGossipSignallerBuilder");
-        let endpoint = Endpoint::builder()
-            .discovery_n0()
-            .alpns(vec![DIRECT_MESSAGE_ALPN.to_vec(), GOSSIP_ALPN.to_vec()])
-            .bind()
-            .await?;
+    /// The endpoint reaches peers as the `options` say, see [`with_discovery`].
+    ///
+    /// With a `persistent` identity the node id stays the same across launches, so invites to its rooms do too.
+    pub async fn new(options: &EndpointOptions, persistent: bool) -> anyhow::Result<Self> {
+        info!("Creating new IrohGossipSignallerBuilder ({options:?}, persistent: {persistent})");
+        let stored_key = if persistent { identity::load() } else { None };
+        let mut builder =
+            Endpoint::builder().alpns(vec![DIRECT_MESSAGE_ALPN.to_vec(), GOSSIP_ALPN.to_vec()]);
+        if let Some(secret_key) = stored_key.clone() {
+            builder = builder.secret_key(secret_key);
+        }
+        let endpoint = with_discovery(builder, options)?.bind().await?;
+        if persistent && stored_key.is_none() {
+            identity::store(endpoint.secret_key());
+        }
         let iroh_id = endpoint.node_id();
         let matchbox_id = PeerId(uuid::Uuid::new_v4());
         info!("Iroh ID: {iroh_id}");
@@ -49,25 +81,167 @@ impl IrohGossipSignallerBuilder {
         direct_message_send.set_overflow(true);
         direct_message_recv.set_overflow(true);
         let direct_message_recv = direct_message_recv.deactivate();
+        let (mut failure_send, failure_recv) = async_broadcast::broadcast(64);
+        failure_send.set_overflow(true);
+        let failure_recv = failure_recv.deactivate();
+        let direct_messages =
+            DirectMessages::new(endpoint.clone(), direct_message_send, failure_send);
+        let (mut reconnect_send, reconnect_recv) = async_broadcast::broadcast(64);
+        reconnect_send.set_overflow(true);
+        let reconnect_recv = reconnect_recv.deactivate();
 
-        let _router = Router::builder(endpoint.clone())
+        let router = Router::builder(endpoint.clone())
             .accept(GOSSIP_ALPN, gossip.clone())
-            .accept(
-                DIRECT_MESSAGE_ALPN,
-                DirectMessageProtocol(direct_message_send),
-            )
+            .accept(DIRECT_MESSAGE_ALPN, direct_messages.clone())
             .spawn();
         Ok(Self {
-            _router,
+            router,
             gossip,
             endpoint,
+            direct_messages,
             matchbox_id,
             iroh_id,
             direct_message_recv,
+            reconnect_send,
+            reconnect_recv,
+            failure_recv,
+            refusal: Default::default(),
+            nodes: Default::default(),
+        })
+    }
+
+    /// Why a peer running an incompatible build was refused, if one showed up since the last call.
+    pub fn take_refusal(&self) -> Option<String> {
+        self.refusal.lock().unwrap().take()
+    }
+
+    /// Forgets a peer whose WebRTC connection dropped, so the next gossip message from it
+    /// is treated as a new peer and the connection gets negotiated again.
+    pub fn reconnect(&self, peer: PeerId) {
+        if let Err(e) = self.reconnect_send.try_broadcast(peer) {
+            warn!("Failed to request reconnection to {peer}: {e}");
+        }
+    }
+
+    /// Whether the iroh connection to `peer` is direct or goes through a relay.
+    pub fn path(&self, peer: PeerId) -> &'static str {
+        let Some(node_id) = self.nodes.lock().unwrap().get(&peer).copied() else {
+            return "path unknown";
+        };
+        match self
+            .endpoint
+            .remote_info(node_id)
+            .map(|info| info.conn_type)
+        {
+            Some(ConnectionType::Direct(_)) => "direct",
+            Some(ConnectionType::Relay(_)) => "relayed",
+            Some(ConnectionType::Mixed(..)) => "direct + relayed",
+            Some(ConnectionType::None) | None => "not connected",
+        }
+    }
+
+    /// Advertises `record` on the room directory as long as the returned handle lives.
+    pub fn advertise(
+        &self,
+        bootstrap: Vec<PublicKey>,
+        record: tokio::sync::watch::Receiver<RoomRecord>,
+    ) -> n0_future::task::AbortOnDropHandle<()> {
+        let gossip = self.gossip.clone();
+        let host_key = self.endpoint.secret_key().clone();
+        n0_future::task::AbortOnDropHandle::new(n0_future::task::spawn(async move {
+            if let Err(e) = room_browser::advertise(&gossip, &host_key, bootstrap, record).await {
+                error!("Failed to advertise the room: {e:#}");
+            }
+        }))
+    }
+
+    /// Stops accepting connections and closes the endpoint, ending every gossip subscription on it.
+    pub async fn shutdown(&self) -> anyhow::Result<()> {
+        self.router.shutdown().await?;
+        Ok(())
+    }
+
+    /// Looks up the ticket a short code stands for.
+    pub async fn resolve_room_code(&self, code: ShortCode) -> anyhow::Result<RoomTicket> {
+        room_code::resolve(&self.endpoint, code).await
+    }
+
+    /// How other nodes can reach this one, as put into room tickets.
+    pub async fn node_addr(&self) -> anyhow::Result<NodeAddr> {
+        self.endpoint.node_addr().await
+    }
+}
+
+/// How iroh endpoints reach their peers.
+#[derive(Clone, Debug)]
+pub struct EndpointOptions {
+    /// Only peers on the local network, without relay or discovery servers.
+    pub lan: bool,
+    /// Relays to fall back to when a direct connection fails.
+    pub relay_mode: RelayMode,
+}
+
+impl EndpointOptions {
+    pub fn from_args(args: &Args) -> anyhow::Result<Self> {
+        let relay_mode = match args.relay {
+            RelayChoice::Default if args.relay_urls.is_empty() => RelayMode::Default,
+            RelayChoice::Disabled => {
+                if cfg!(target_arch = "wasm32") {
+                    anyhow::bail!("Browsers can only reach peers through relays");
+                }
+                RelayMode::Disabled
+            }
+            // giving relay URLs is enough to use them
+            RelayChoice::Default | RelayChoice::Custom => {
+                let urls = args
+                    .relay_urls
+                    .iter()
+                    .map(|url| {
+                        url.parse::<RelayUrl>()
+                            .with_context(|| format!("Invalid relay URL: {url}"))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                if urls.is_empty() {
+                    anyhow::bail!("Custom relays need at least one relay URL");
+                }
+                RelayMode::Custom(urls.into_iter().collect())
+            }
+        };
+        Ok(Self {
+            lan: args.lan,
+            relay_mode,
         })
     }
 }
 
+/// Finds peers through n0's discovery and the configured relays,
+/// or in `lan` mode only on the local network and through the addresses in the room ticket.
+pub fn with_discovery(
+    builder: iroh::endpoint::Builder,
+    options: &EndpointOptions,
+) -> anyhow::Result<iroh::endpoint::Builder> {
+    if options.lan {
+        lan_endpoint(builder)
+    } else {
+        Ok(builder
+            .relay_mode(options.relay_mode.clone())
+            .discovery_n0())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn lan_endpoint(builder: iroh::endpoint::Builder) -> anyhow::Result<iroh::endpoint::Builder> {
+    Ok(builder
+        .relay_mode(RelayMode::Disabled)
+        .discovery_local_network())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn lan_endpoint(_builder: iroh::endpoint::Builder) -> anyhow::Result<iroh::endpoint::Builder> {
+    // browsers cannot open sockets on the local network, they only reach peers through relays
+    anyhow::bail!("LAN play is not available in the browser")
+}
+
 #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
 impl SignallerBuilder for IrohGossipSignallerBuilder {
@@ -76,13 +250,9 @@ impl SignallerBuilder for IrohGossipSignallerBuilder {
         _attempts: Option<u16>,
         room_url: String,
     ) -> Result<Box<dyn Signaller>, SignalingError> {
-        let room_pubkey: Option<PublicKey> = if room_url.is_empty() {
-            None
-        } else {
-            Some(room_url.parse().map_err(to_user_error)?)
-        };
+        let ticket: RoomTicket = room_url.parse().map_err(to_user_error)?;
         for i in 0.._attempts.unwrap_or(3) {
-            match self.try_new_signaller(room_pubkey).await {
+            match self.try_new_signaller(&ticket).await {
                 Ok(signaller) => {
                     return Ok(Box::new(signaller));
                 }
@@ -103,17 +273,22 @@ fn to_user_error<E: std::fmt::Debug>(e: E) -> SignalingError {
 }
 
 impl IrohGossipSignallerBuilder {
-    async fn try_new_signaller(
-        &self,
-        room_pubkey: Option<PublicKey>,
-    ) -> anyhow::Result<IrohGossipSignaller> {
-        info!("Creating new signaller");
-        let bootstrap = room_pubkey.iter().cloned().collect();
-        info!(
-            "Subscribing to gossip topic {:?} with bootstrap: {:?}",
-            GOSSIP_TOPIC_ID, bootstrap
-        );
-        let mut gossip_topic = self.gossip.subscribe(GOSSIP_TOPIC_ID, bootstrap)?;
+    async fn try_new_signaller(&self, ticket: &RoomTicket) -> anyhow::Result<IrohGossipSignaller> {
+        let room = ticket.room;
+        info!("Creating new signaller for room {room}");
+        // the host starts the swarm, everybody else joins through it
+        let bootstrap = if room.host == self.iroh_id {
+            vec![]
+        } else {
+            if ticket.has_addresses() {
+                // dial the addresses from the ticket instead of waiting for discovery
+                self.endpoint.add_node_addr(ticket.host_addr())?;
+            }
+            vec![room.host]
+        };
+        let topic = room.topic();
+        info!("Subscribing to gossip topic {topic:?} with bootstrap: {bootstrap:?}");
+        let mut gossip_topic = self.gossip.subscribe(topic, bootstrap)?;
         info!("Joining gossip topic...");
         gossip_topic.joined().await?;
         info!("Connected to gossip topic.");
@@ -130,6 +305,8 @@ impl IrohGossipSignallerBuilder {
                 req_recv,
                 event_send,
                 self.direct_message_recv.activate_cloned(),
+                self.reconnect_recv.activate_cloned(),
+                self.failure_recv.activate_cloned(),
             )
             .then(|r| async move {
                 match r {
@@ -160,9 +337,11 @@ impl IrohGossipSignallerBuilder {
         event_send: tokio::sync::mpsc::Sender<PeerEvent>,
         // get direct messages from other clients
         mut direct_message_recv: async_broadcast::Receiver<(PublicKey, PeerEvent)>,
+        // peers to negotiate a new connection with
+        mut reconnect_recv: async_broadcast::Receiver<PeerId>,
+        // nodes whose signal stream lost signals
+        mut failure_recv: async_broadcast::Receiver<PublicKey>,
     ) -> anyhow::Result<()> {
-        const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
-        const STALE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(15);
         info!("Spawning signal task");
 
         // send AssignedId into client
@@ -179,6 +358,10 @@ impl IrohGossipSignallerBuilder {
 
         let mut refresh_interval = n0_future::time::interval(REFRESH_INTERVAL);
 
+        let handshake = Handshake::local();
+        // incompatible peers keep announcing themselves, only report them once
+        let mut refused = BTreeSet::<PublicKey>::new();
+
         loop {
             tokio::select! {
                 gossip_msg = gossip_recv.next().fuse() => {
@@ -193,10 +376,24 @@ impl IrohGossipSignallerBuilder {
                             anyhow::bail!("Gossip receiver lagged");
                         }
                         Event::Gossip(GossipEvent::Received(Message { content: gossip_msg, ..})) => {
-                            let GossipMessage{iroh_id, matchbox_id, ..} = serde_json::from_slice(&gossip_msg)?;
+                            let Ok(GossipMessage{iroh_id, matchbox_id, handshake: remote}) = serde_json::from_slice(&gossip_msg) else {
+                                warn!("Ignoring malformed gossip message");
+                                continue;
+                            };
+                            if let Err(reason) = handshake.check(remote.as_ref()) {
+                                // never mapping the ids means no connection forms, and its signals are dropped
+                                if refused.insert(iroh_id) {
+                                    warn!("Refusing peer {iroh_id}: {reason}");
+                                    *self.refusal.lock().unwrap() = Some(reason);
+                                    // make sure the other side hears of us and refuses too
+                                    self.send_gossip_message(&gossip_send).await?;
+                                }
+                                continue;
+                            }
                             let now = Instant::now();
                             let is_new = !matchbox_to_iroh.contains_key(&matchbox_id);
                             matchbox_to_iroh.insert(matchbox_id, (iroh_id, now));
+                            self.nodes.lock().unwrap().insert(matchbox_id, iroh_id);
                             iroh_to_matchbox.insert(iroh_id, (matchbox_id, now));
                             if is_new {
                                 info!("New peer connection: matchbox ID {matchbox_id} = Iroh ID {iroh_id}");
@@ -228,9 +425,10 @@ impl IrohGossipSignallerBuilder {
                         }
                         PeerRequest::Signal { receiver, data } => {
                             // send direct message to MatchboxSignalProtocol
-                            match self.send_direct_message(receiver, data, &matchbox_to_iroh).await {
+                            match self.send_direct_message(receiver, data, &matchbox_to_iroh) {
                                 Ok(_) => {}
                                 Err(e) => {
+                                    // a known peer also comes back through the failure receiver, which starts over
                                     error!("Error sending direct message: {e:#?}");
                                 }
                             }
@@ -258,6 +456,32 @@ impl IrohGossipSignallerBuilder {
                         warn!("Received message from {from_iroh_id} with wrong event type: {event:#?}");
                     }
                 }
+                reconnect = reconnect_recv.next().fuse() => {
+                    let Some(peer_id) = reconnect else {
+                        anyhow::bail!("Reconnect receiver stream problem: {:#?}", reconnect);
+                    };
+                    if let Some((node_id, _)) = matchbox_to_iroh.remove(&peer_id) {
+                        info!("Forgetting peer {peer_id} -> {node_id} to reconnect");
+                        self.nodes.lock().unwrap().remove(&peer_id);
+                        iroh_to_matchbox.remove(&node_id);
+                    }
+                    // let the peer know we are still here, its answer makes it a new peer again
+                    self.send_gossip_message(&gossip_send).await?;
+                }
+                failure = failure_recv.next().fuse() => {
+                    let Some(node_id) = failure else {
+                        anyhow::bail!("Failure receiver stream problem: {:#?}", failure);
+                    };
+                    // the negotiation with the peer is missing signals, so it cannot finish
+                    if let Some((peer_id, _)) = iroh_to_matchbox.remove(&node_id) {
+                        info!("Lost signals to {peer_id} -> {node_id}, starting over");
+                        matchbox_to_iroh.remove(&peer_id);
+                        self.nodes.lock().unwrap().remove(&peer_id);
+                        event_send.send(PeerEvent::PeerLeft(peer_id)).await?;
+                        // its next gossip message makes it a new peer again
+                        self.send_gossip_message(&gossip_send).await?;
+                    }
+                }
                 _ = refresh_interval.tick().fuse() => {
                     self.send_gossip_message(&gossip_send).await?;
                     // check for stale connections and send PeerLeft events
@@ -271,6 +495,7 @@ impl IrohGossipSignallerBuilder {
                         info!("Removing dead peer connection: {peer_id} -> {node_id}");
                         matchbox_to_iroh.remove(&peer_id);
                         iroh_to_matchbox.remove(&node_id);
+                        self.nodes.lock().unwrap().remove(&peer_id);
                         event_send.send(PeerEvent::PeerLeft(peer_id)).await?;
                     }
                 }
@@ -283,22 +508,19 @@ impl IrohGossipSignallerBuilder {
         let message = GossipMessage {
             matchbox_id: self.matchbox_id,
             iroh_id: self.iroh_id,
+            handshake: Some(Handshake::local()),
         };
         let message = serde_json::to_vec(&message)?;
         gossip_send.broadcast(message.into()).await?;
         Ok(())
     }
 
-    async fn send_direct_message(
+    fn send_direct_message(
         &self,
         receiver: PeerId,
         data: PeerSignal,
         matchbox_to_iroh: &BTreeMap<PeerId, (PublicKey, Instant)>,
     ) -> anyhow::Result<()> {
-        let event = PeerEvent::Signal {
-            sender: self.matchbox_id,
-            data,
-        };
         let target_node_id = matchbox_to_iroh
             .get(&receiver)
             .map(|(node_id, _)| *node_id)
@@ -308,12 +530,12 @@ impl IrohGossipSignallerBuilder {
         Sending direct message to:
             Matchbox ID: {receiver} 
             Iroh     ID: {target_node_id}
-            Event: {event:#?}
+            Signal: {data:#?}
         "
         );
 
-        send_direct_message(&self.endpoint, target_node_id, event).await?;
-        Ok(())
+        self.direct_messages
+            .send(target_node_id, self.matchbox_id, data)
     }
 }
 
@@ -321,6 +543,9 @@ impl IrohGossipSignallerBuilder {
 struct GossipMessage {
     matchbox_id: PeerId,
     iroh_id: PublicKey,
+    /// Missing from builds older than the handshake.
+    #[serde(default)]
+    handshake: Option<Handshake>,
 }
 
 struct IrohGossipSignaller {
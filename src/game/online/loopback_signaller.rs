@@ -0,0 +1,125 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use bevy::prelude::*;
+use matchbox_socket::{
+    PeerEvent, PeerId, PeerRequest, SignalingError, Signaller, SignallerBuilder,
+    async_trait::async_trait,
+};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+/// Peers of every loopback room in this process, by room name.
+static ROOMS: LazyLock<Mutex<HashMap<String, HashMap<PeerId, UnboundedSender<PeerEvent>>>>> =
+    LazyLock::new(Default::default);
+
+/// Signals between sockets of the same process without any network, e.g. two apps in one test.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoopbackSignallerBuilder;
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl SignallerBuilder for LoopbackSignallerBuilder {
+    async fn new_signaller(
+        &self,
+        _attempts: Option<u16>,
+        room_url: String,
+    ) -> Result<Box<dyn Signaller>, SignalingError> {
+        let id = PeerId(uuid::Uuid::new_v4());
+        let (send, recv) = unbounded_channel();
+        _ = send.send(PeerEvent::IdAssigned(id));
+
+        let mut rooms = ROOMS.lock().unwrap();
+        let room = rooms.entry(room_url.clone()).or_default();
+        // like the matchbox server, the peers already in the room start the handshake with the new one
+        for peer in room.values() {
+            _ = peer.send(PeerEvent::NewPeer(id));
+        }
+        room.insert(id, send);
+        info!("Peer {id} joined loopback room {room_url}");
+
+        Ok(Box::new(LoopbackSignaller {
+            id,
+            room: room_url,
+            recv,
+        }))
+    }
+}
+
+/// Makes `own` and `peer` negotiate a new connection, as the iroh signaller does for dropped connections.
+///
+/// Like there, the peer with the larger id starts the handshake, so both sides may ask.
+pub fn reconnect(room: &str, own: PeerId, peer: PeerId) {
+    if own < peer {
+        return; // the other side starts it
+    }
+    let rooms = ROOMS.lock().unwrap();
+    if let Some(socket) = rooms.get(room).and_then(|room| room.get(&own)) {
+        _ = socket.send(PeerEvent::NewPeer(peer));
+    }
+}
+
+/// Tells `peer` and everybody else in `room` that their connections to each other dropped.
+pub fn interrupt(room: &str, peer: PeerId) {
+    let rooms = ROOMS.lock().unwrap();
+    let Some(room) = rooms.get(room) else {
+        return;
+    };
+    let Some(interrupted) = room.get(&peer) else {
+        return;
+    };
+    for (id, socket) in room.iter().filter(|(id, _)| **id != peer) {
+        _ = socket.send(PeerEvent::PeerLeft(peer));
+        _ = interrupted.send(PeerEvent::PeerLeft(*id));
+    }
+}
+
+struct LoopbackSignaller {
+    id: PeerId,
+    room: String,
+    recv: UnboundedReceiver<PeerEvent>,
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl Signaller for LoopbackSignaller {
+    async fn send(&mut self, request: PeerRequest) -> Result<(), SignalingError> {
+        let PeerRequest::Signal { receiver, data } = request else {
+            return Ok(()); // nothing to keep alive
+        };
+        let rooms = ROOMS.lock().unwrap();
+        let Some(peer) = rooms.get(&self.room).and_then(|room| room.get(&receiver)) else {
+            warn!("Loopback peer {receiver} is gone, dropping signal");
+            return Ok(());
+        };
+        _ = peer.send(PeerEvent::Signal {
+            sender: self.id,
+            data,
+        });
+        Ok(())
+    }
+
+    async fn next_message(&mut self) -> Result<PeerEvent, SignalingError> {
+        self.recv
+            .recv()
+            .await
+            .ok_or(SignalingError::StreamExhausted)
+    }
+}
+
+impl Drop for LoopbackSignaller {
+    fn drop(&mut self) {
+        let mut rooms = ROOMS.lock().unwrap();
+        let Some(room) = rooms.get_mut(&self.room) else {
+            return;
+        };
+        room.remove(&self.id);
+        for peer in room.values() {
+            _ = peer.send(PeerEvent::PeerLeft(self.id));
+        }
+        if room.is_empty() {
+            rooms.remove(&self.room);
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use std::{collections::HashMap, time::Duration};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use matchbox_socket::{PeerId, WebRtcSocket};
+use serde::{Deserialize, Serialize};
+use web_time::Instant;
+
+use crate::{
+    args::Args,
+    game::{
+        field::level::{Level, LevelHandles},
+        rng::MatchRng,
+        rules::MatchRules,
+    },
+};
+
+use super::{IrohSocket, connection::FRAME_RATE, handshake::Handshake, network_role::NetworkRole};
+
+/// Reliable channel used to agree on the match setup before the GGRS session takes channel 0.
+pub const SETUP_CHANNEL: usize = 1;
+
+/// Round trips measured to every peer before the automatic input delay is picked.
+const ROUND_TRIP_SAMPLES: usize = 5;
+
+/// A ping without an answer for this long is sent again.
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Upper bound of the automatic input delay, rollbacks hide the rest of the latency.
+const MAX_AUTO_INPUT_DELAY: usize = 6;
+
+/// Everything the peers have to agree on before the simulation starts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatchSetup {
+    pub level: String,
+    pub level_digest: u64,
+    pub rules: MatchRules,
+    /// Seed for the [`MatchRng`] of every peer.
+    pub seed: u64,
+    /// Peers playing the match, in the order of their player handles.
+    pub players: Vec<PeerId>,
+    /// Build of the host, which every peer has to run as well.
+    pub handshake: Handshake,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum SetupMessage {
+    /// A peer running this build wants to play in the host's match.
+    Join(Handshake),
+    /// A peer running this build only wants to watch the host's match.
+    Spectate(Handshake),
+    /// The host announces the setup it picked.
+    Offer(MatchSetup),
+    /// The peer has loaded the same setup.
+    Accept,
+    /// The peer cannot play the offered setup.
+    Reject { reason: String },
+    /// Measures the round trip time for the automatic input delay.
+    Ping(u32),
+    /// Answer to the [`SetupMessage::Ping`] with the same number.
+    Pong(u32),
+}
+
+impl SetupMessage {
+    fn send(&self, socket: &mut WebRtcSocket, peer: PeerId) {
+        match serde_json::to_vec(self) {
+            Ok(packet) => socket
+                .channel_mut(SETUP_CHANNEL)
+                .send(packet.into_boxed_slice(), peer),
+            Err(e) => error!("Failed to encode setup message: {e:#?}"),
+        }
+    }
+
+    fn receive(socket: &mut WebRtcSocket) -> Vec<(PeerId, Self)> {
+        socket
+            .channel_mut(SETUP_CHANNEL)
+            .receive()
+            .into_iter()
+            .filter_map(|(peer, packet)| match serde_json::from_slice(&packet) {
+                Ok(message) => Some((peer, message)),
+                Err(e) => {
+                    warn!("Received invalid setup message from {peer}: {e:#?}");
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Progress of the setup exchange, kept across frames.
+#[derive(Default)]
+pub struct Negotiation {
+    /// Peers we told whether we play or watch.
+    announced: Vec<PeerId>,
+    /// Peers that asked the host to play.
+    joined: Vec<PeerId>,
+    /// Peers that asked the host to watch.
+    spectators: Vec<PeerId>,
+    offered: Option<MatchSetup>,
+    accepted: Vec<PeerId>,
+    /// Sender of the offer we accepted.
+    host: Option<PeerId>,
+    agreed: Option<MatchSetup>,
+    /// Ping in flight to each peer, and when it was sent.
+    pings: HashMap<PeerId, (u32, Instant)>,
+    next_ping: u32,
+    /// Round trip times measured to each peer.
+    round_trips: HashMap<PeerId, Vec<Duration>>,
+}
+
+impl Negotiation {
+    /// Spectators the host streams the match to.
+    pub fn spectators(&self) -> &[PeerId] {
+        &self.spectators
+    }
+
+    /// The host whose offer was accepted.
+    pub fn host(&self) -> Option<PeerId> {
+        self.host
+    }
+
+    /// Median round trip time to the slowest of `peers`, once enough pings came back from all of them.
+    pub fn round_trip(&self, peers: &[PeerId]) -> Option<Duration> {
+        peers
+            .iter()
+            .map(|peer| {
+                let mut samples = self.round_trips.get(peer)?.clone();
+                if samples.len() < ROUND_TRIP_SAMPLES {
+                    return None;
+                }
+                samples.sort();
+                Some(samples[samples.len() / 2])
+            })
+            .try_fold(Duration::ZERO, |slowest, round_trip| {
+                Some(slowest.max(round_trip?))
+            })
+    }
+
+    /// Answers the pings of the `peers` and keeps pinging them, returning the other messages.
+    fn measure_round_trips(
+        &mut self,
+        socket: &mut WebRtcSocket,
+        peers: &[PeerId],
+        messages: Vec<(PeerId, SetupMessage)>,
+    ) -> Vec<(PeerId, SetupMessage)> {
+        let now = Instant::now();
+        let messages = messages
+            .into_iter()
+            .filter(|(peer, message)| match message {
+                SetupMessage::Ping(ping) => {
+                    SetupMessage::Pong(*ping).send(socket, *peer);
+                    false
+                }
+                SetupMessage::Pong(pong) => {
+                    if let Some((ping, sent)) = self.pings.get(peer)
+                        && ping == pong
+                    {
+                        let round_trip = now.duration_since(*sent);
+                        self.round_trips.entry(*peer).or_default().push(round_trip);
+                        self.pings.remove(peer);
+                    }
+                    false
+                }
+                _ => true,
+            })
+            .collect();
+        for peer in peers {
+            let measured = self.round_trips.get(peer).map_or(0, Vec::len);
+            let waiting = self
+                .pings
+                .get(peer)
+                .is_some_and(|(_, sent)| now.duration_since(*sent) < PING_TIMEOUT);
+            if ROUND_TRIP_SAMPLES <= measured || waiting {
+                continue;
+            }
+            self.next_ping += 1;
+            SetupMessage::Ping(self.next_ping).send(socket, *peer);
+            self.pings.insert(*peer, (self.next_ping, now));
+        }
+        messages
+    }
+}
+
+/// Keeps answering pings once the match started, for peers still measuring before they start theirs.
+pub fn answer_pings(mut socket: ResMut<IrohSocket>) {
+    for (peer, message) in SetupMessage::receive(&mut socket) {
+        if let SetupMessage::Ping(ping) = message {
+            SetupMessage::Pong(ping).send(&mut socket, peer);
+        }
+    }
+}
+
+/// Input delay hiding the time an input takes to reach the other players.
+pub fn auto_input_delay(round_trip: Duration) -> usize {
+    let frames = (round_trip.as_secs_f64() / 2.0 * FRAME_RATE).ceil() as usize;
+    frames.min(MAX_AUTO_INPUT_DELAY)
+}
+
+/// What the host builds its offer from, and what a client checks an offer against.
+#[derive(SystemParam)]
+pub struct SetupSource<'w> {
+    pub args: Res<'w, Args>,
+    pub rules: Res<'w, MatchRules>,
+    pub level_handles: Res<'w, LevelHandles>,
+    pub levels: Res<'w, Assets<Level>>,
+}
+
+/// Drives the setup exchange with the connected `peers`.
+///
+/// Every other peer tells the host whether it plays or watches. The host offers its setup once the chosen level
+/// has enough players and waits for every player to accept it, spectators get the same offer but are not waited for.
+/// Players beyond the level's count are turned away, and a player leaving after the offer fails the setup for everybody.
+/// Returns the agreed setup once this side is ready, or an error message if the peers are incompatible.
+pub fn negotiate(
+    socket: &mut WebRtcSocket,
+    peers: &[PeerId],
+    role: NetworkRole,
+    negotiation: &mut Negotiation,
+    source: &SetupSource,
+) -> Result<Option<MatchSetup>, String> {
+    if !source.level_handles.all_loaded(&source.levels) {
+        return Ok(None); // levels are still loading
+    }
+    let messages = SetupMessage::receive(socket);
+    let messages = negotiation.measure_round_trips(socket, peers, messages);
+
+    match role {
+        NetworkRole::Host => {
+            for (peer, message) in messages {
+                if let SetupMessage::Join(handshake) | SetupMessage::Spectate(handshake) = &message
+                    && let Err(reason) = handshake.check(Some(&Handshake::local()))
+                {
+                    // the reason is worded for the peer, which runs the other build
+                    warn!("Turning away peer {peer}: {reason}");
+                    SetupMessage::Reject { reason }.send(socket, peer);
+                    continue;
+                }
+                match message {
+                    SetupMessage::Join(_) if negotiation.joined.contains(&peer) => {}
+                    SetupMessage::Join(_) if negotiation.offered.is_some() => {
+                        info!("Turning away peer {peer}, the match is already set up");
+                        SetupMessage::Reject {
+                            reason: "The match is already full".into(),
+                        }
+                        .send(socket, peer);
+                    }
+                    SetupMessage::Join(_) => negotiation.joined.push(peer),
+                    SetupMessage::Spectate(_) => {
+                        if !negotiation.spectators.contains(&peer) {
+                            info!("Peer {peer} joined as a spectator");
+                            negotiation.spectators.push(peer);
+                            if let Some(setup) = &negotiation.offered {
+                                SetupMessage::Offer(setup.clone()).send(socket, peer);
+                            }
+                        }
+                    }
+                    SetupMessage::Accept => {
+                        if !negotiation.accepted.contains(&peer) {
+                            negotiation.accepted.push(peer);
+                        }
+                    }
+                    SetupMessage::Reject { reason } if negotiation.spectators.contains(&peer) => {
+                        warn!("Spectator {peer} cannot watch: {reason}");
+                        negotiation
+                            .spectators
+                            .retain(|spectator| *spectator != peer);
+                    }
+                    SetupMessage::Reject { reason } => return Err(reason),
+                    SetupMessage::Offer(_) => warn!("Ignoring setup offer from a client"),
+                    SetupMessage::Ping(_) | SetupMessage::Pong(_) => {} // already answered
+                }
+            }
+            // only peers that are still connected count
+            negotiation.spectators.retain(|peer| peers.contains(peer));
+            if let Some(setup) = &negotiation.offered {
+                // the roster is fixed once offered, a player leaving now ends the setup for everybody
+                if let Some(handle) = setup
+                    .players
+                    .iter()
+                    .position(|player| Some(*player) != socket.id() && !peers.contains(player))
+                {
+                    let reason = format!("Player {} left before the match started", handle + 1);
+                    let others = negotiation.joined.iter().chain(&negotiation.spectators);
+                    for peer in others.filter(|peer| peers.contains(peer)) {
+                        SetupMessage::Reject {
+                            reason: reason.clone(),
+                        }
+                        .send(socket, *peer);
+                    }
+                    return Err(reason);
+                }
+            } else {
+                negotiation.joined.retain(|peer| peers.contains(peer));
+            }
+
+            if negotiation.offered.is_none() {
+                let Some(own_id) = socket.id() else {
+                    return Ok(None);
+                };
+                let level = source
+                    .level_handles
+                    .get(&source.args.level, &source.levels)
+                    .ok_or_else(|| format!("Unknown level: {}", source.args.level))?;
+                let num_players = negotiation.joined.len() + 1;
+                if num_players < level.num_players() {
+                    return Ok(None); // wait for more players
+                }
+                // first come, first served
+                for peer in negotiation.joined.split_off(level.num_players() - 1) {
+                    info!("Turning away peer {peer}, {} is full", source.args.level);
+                    SetupMessage::Reject {
+                        reason: format!(
+                            "The match is already full, {} can play",
+                            level.num_players()
+                        ),
+                    }
+                    .send(socket, peer);
+                }
+                let mut players = negotiation.joined.clone();
+                players.push(own_id);
+                players.sort();
+                let setup = MatchSetup {
+                    level: source.args.level.clone(),
+                    level_digest: level.digest,
+                    rules: source.rules.clone(),
+                    seed: MatchRng::random_seed(),
+                    players,
+                    handshake: Handshake::local(),
+                };
+                info!("Offering match setup: {setup:?}");
+                for peer in negotiation.joined.iter().chain(&negotiation.spectators) {
+                    SetupMessage::Offer(setup.clone()).send(socket, *peer);
+                }
+                negotiation.offered = Some(setup);
+            }
+
+            let all_accepted = negotiation
+                .joined
+                .iter()
+                .all(|peer| negotiation.accepted.contains(peer));
+            Ok(if all_accepted {
+                negotiation.offered.clone()
+            } else {
+                None
+            })
+        }
+        NetworkRole::Client | NetworkRole::Spectator => {
+            // we do not know the host yet, so tell everybody and let the others ignore it
+            let greeting = match role {
+                NetworkRole::Spectator => SetupMessage::Spectate(Handshake::local()),
+                _ => SetupMessage::Join(Handshake::local()),
+            };
+            for peer in peers {
+                if !negotiation.announced.contains(peer) {
+                    greeting.send(socket, *peer);
+                    negotiation.announced.push(*peer);
+                }
+            }
+
+            // the host is whoever sends the offer
+            for (host, message) in messages {
+                let setup = match message {
+                    SetupMessage::Offer(setup) => setup,
+                    // only the host turns peers away
+                    SetupMessage::Reject { reason } => return Err(reason),
+                    _ => continue, // greetings are only meant for the host
+                };
+                info!("Received match setup: {setup:?}");
+                if let Err(reason) = check_setup(&setup, &source.level_handles, &source.levels) {
+                    SetupMessage::Reject {
+                        reason: reason.clone(),
+                    }
+                    .send(socket, host);
+                    return Err(reason);
+                }
+                SetupMessage::Accept.send(socket, host);
+                negotiation.host = Some(host);
+                negotiation.agreed = Some(setup);
+            }
+            Ok(negotiation.agreed.clone())
+        }
+    }
+}
+
+fn check_setup(
+    setup: &MatchSetup,
+    level_handles: &LevelHandles,
+    levels: &Assets<Level>,
+) -> Result<(), String> {
+    Handshake::local().check(Some(&setup.handshake))?;
+    let level = level_handles
+        .get(&setup.level, levels)
+        .ok_or_else(|| format!("Unknown level: {}", setup.level))?;
+    if level.digest != setup.level_digest {
+        return Err(format!("Level {} differs between the peers", setup.level));
+    }
+    Ok(())
+}
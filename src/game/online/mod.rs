@@ -1,66 +1,244 @@
-use std::sync::Arc;
+use std::time::Duration;
 
 use bevy::prelude::*;
 use bevy_ggrs::*;
 use bevy_wasm_tasks::Tasks;
+use futures::future::{AbortHandle, Abortable};
 use iroh_gossip_signaller::IrohGossipSignallerBuilder;
-use matchbox_socket::{WebRtcSocket, WebRtcSocketBuilder};
+use matchbox_socket::{PeerId, WebRtcSocket};
+use web_time::Instant;
 
-use crate::args::Args;
+use crate::args::{Args, InputDelay};
 
-use super::{Config, GameState};
+use super::{
+    Config, GameState,
+    field::level::{CurrentLevel, Level, LevelHandles},
+    menu::notification::Notifications,
+    rng::MatchRng,
+};
+use match_setup::{Negotiation, SetupSource};
+use network_role::NetworkRole;
 
+pub mod connection;
 pub mod direct_message;
+pub mod handshake;
+pub mod identity;
 pub mod iroh_gossip_signaller;
+pub mod loopback_signaller;
+pub mod match_setup;
 pub mod network_role;
+pub mod network_stats;
+pub mod room;
+pub mod room_browser;
+pub mod room_code;
+pub mod signalling;
 
 pub struct OnlinePlugin;
 
 impl Plugin for OnlinePlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(bevy_wasm_tasks::TasksPlugin::default())
-            .add_systems(
-                OnEnter(GameState::Matchmaking),
-                start_matchbox_socket.run_if(p2p_mode),
+        app.add_plugins((
+            bevy_wasm_tasks::TasksPlugin::default(),
+            connection::ConnectionPlugin,
+            network_stats::NetworkStatsPlugin,
+            room_browser::RoomBrowserPlugin,
+        ))
+        .init_resource::<Spectators>()
+        .add_systems(
+            OnEnter(GameState::Matchmaking),
+            start_matchbox_socket.run_if(p2p_mode),
+        )
+        .add_systems(
+            Update,
+            (
+                wait_for_players
+                    .run_if(resource_exists::<Negotiation>)
+                    .run_if(not(resource_exists::<MatchmakingError>)),
+                report_refused_peers.run_if(resource_exists::<Signalling>),
+                time_out_matchmaking.run_if(resource_exists::<MatchmakingDeadline>),
+                start_synctest_session.run_if(synctest_mode),
             )
-            .add_systems(
-                Update,
-                (
-                    wait_for_players.run_if(p2p_mode),
-                    start_synctest_session.run_if(synctest_mode),
-                )
-                    .run_if(in_state(GameState::Matchmaking)),
-            );
+                .run_if(in_state(GameState::Matchmaking)),
+        )
+        .add_systems(OnEnter(GameState::Lobby), leave_room)
+        .add_systems(
+            Update,
+            match_setup::answer_pings
+                .run_if(in_state(GameState::InGame))
+                .run_if(resource_exists::<IrohSocket>),
+        );
     }
 }
 
 #[derive(Resource, Deref, DerefMut)]
 pub struct IrohSocket(WebRtcSocket);
 
+/// Invite to the current match's room, which the host shares.
+#[derive(Resource)]
+pub struct Room {
+    /// Everything needed to join.
+    pub code: String,
+    /// A short code resolved to `code` through the host's beacon, for reading out or typing.
+    pub short_code: String,
+    /// Whether the web build can join through an invite link.
+    pub web_link: bool,
+}
+
+impl Room {
+    /// Room of a backend where the name is all there is to the invite.
+    pub fn named(name: String) -> Self {
+        Self {
+            code: name.clone(),
+            short_code: name,
+            web_link: false,
+        }
+    }
+}
+
+/// Peers watching the match this host streams.
+#[derive(Resource, Default)]
+pub struct Spectators(pub Vec<PeerId>);
+
+/// Handle on the signaller, kept to negotiate connections again when they drop mid-match.
 #[derive(Resource)]
-pub struct IrohId(pub iroh::PublicKey);
+pub struct Signalling(pub IrohGossipSignallerBuilder);
 
-fn start_matchbox_socket(tasks: Tasks, args: Res<Args>) {
-    let iroh_address = args.iroh.clone();
+/// Reason why matchmaking cannot continue, shown on the matchmaking screen.
+#[derive(Resource)]
+pub struct MatchmakingError(pub String);
+
+/// Stops the task opening the room and running the socket's message loop when the room is left.
+#[derive(Resource)]
+struct RoomTask(AbortHandle);
+
+/// When matchmaking gives up if the match has not started yet.
+#[derive(Resource)]
+struct MatchmakingDeadline(Instant);
+
+fn start_matchbox_socket(
+    mut commands: Commands,
+    tasks: Tasks,
+    args: Res<Args>,
+    role: Res<NetworkRole>,
+) {
+    commands.insert_resource(Negotiation::default());
+    if 0 < args.matchmaking_timeout {
+        commands.insert_resource(MatchmakingDeadline(
+            Instant::now() + Duration::from_secs(args.matchmaking_timeout),
+        ));
+    }
+    let (abort_handle, abort_registration) = AbortHandle::new_pair();
+    commands.insert_resource(RoomTask(abort_handle));
+    let ice_server = signalling::ice_server(&args);
+    let args = args.clone();
+    let role = *role;
     tasks.spawn_auto(async move |x| {
-        let signaller_builder = IrohGossipSignallerBuilder::new().await.unwrap();
-        let iroh_id = signaller_builder.iroh_id;
-        let builder = WebRtcSocketBuilder::new(iroh_address)
-            .signaller_builder(Arc::new(signaller_builder))
-            .add_unreliable_channel();
-        info!("Starting matchbox socket");
-        let (socket, message_loop_fut) = builder.build();
-        x.submit_on_main_thread(move |ctx| {
-            ctx.world.insert_resource(IrohSocket(socket));
-            ctx.world.insert_resource(IrohId(iroh_id));
-        });
-        _ = message_loop_fut.await;
+        let room = async move {
+            let opened = match signalling::open_room(args, role).await {
+                Ok(opened) => opened,
+                Err(reason) => {
+                    error!("Failed to open the room: {reason}");
+                    x.submit_on_main_thread(move |ctx| {
+                        ctx.world.insert_resource(MatchmakingError(reason));
+                    });
+                    return;
+                }
+            };
+            let mut builder = opened
+                .builder
+                .add_unreliable_channel()
+                .add_reliable_channel();
+            if let Some(ice_server) = ice_server {
+                builder = builder.ice_server(ice_server);
+            }
+            info!("Starting matchbox socket");
+            let (socket, message_loop_fut) = builder.build();
+            // dropped with the room task, which stops handing out the ticket
+            let _beacon = opened.beacon;
+            x.submit_on_main_thread(move |ctx| {
+                ctx.world.insert_resource(IrohSocket(socket));
+                ctx.world.insert_resource(opened.room);
+                if let Some(signalling) = opened.signalling {
+                    ctx.world.insert_resource(signalling);
+                }
+            });
+            if let Err(e) = message_loop_fut.await {
+                error!("Socket message loop stopped: {e:#?}");
+                x.submit_on_main_thread(move |ctx| {
+                    ctx.world.insert_resource(MatchmakingError(format!(
+                        "Lost the connection to the room: {e}"
+                    )));
+                });
+            }
+        };
+        // aborting drops the socket's message loop along with the signaller and its gossip subscription
+        if Abortable::new(room, abort_registration).await.is_err() {
+            info!("Left the room");
+        }
     });
 }
 
+/// Gives up on a match that did not start in time.
+fn time_out_matchmaking(
+    mut commands: Commands,
+    deadline: Res<MatchmakingDeadline>,
+    args: Res<Args>,
+    error: Option<Res<MatchmakingError>>,
+) {
+    if error.is_none() && deadline.0 <= Instant::now() {
+        warn!("Matchmaking timed out");
+        commands.insert_resource(MatchmakingError(format!(
+            "The match did not start within {} seconds",
+            args.matchmaking_timeout
+        )));
+    }
+}
+
+/// Closes the socket and the signalling of the last room, so a new one can be hosted or joined.
+fn leave_room(
+    mut commands: Commands,
+    tasks: Tasks,
+    room_task: Option<Res<RoomTask>>,
+    signalling: Option<Res<Signalling>>,
+) {
+    if let Some(room_task) = room_task {
+        room_task.0.abort();
+    }
+    if let Some(signalling) = signalling {
+        let signalling = signalling.0.clone();
+        tasks.spawn_auto(async move |_| {
+            if let Err(e) = signalling.shutdown().await {
+                warn!("Failed to shut down signalling: {e:#}");
+            }
+        });
+    }
+    commands.remove_resource::<RoomTask>();
+    commands.remove_resource::<IrohSocket>();
+    commands.remove_resource::<Signalling>();
+    commands.remove_resource::<Room>();
+    commands.remove_resource::<Negotiation>();
+    commands.remove_resource::<MatchmakingDeadline>();
+    commands.remove_resource::<MatchmakingError>();
+    commands.remove_resource::<bevy_ggrs::Session<Config>>();
+    commands.insert_resource(Spectators::default());
+}
+
+/// Tells the player why a peer that tried to join was refused.
+///
+/// Matchmaking goes on, a stranger running another build must not end the match for the others.
+fn report_refused_peers(signalling: Res<Signalling>, mut notifications: ResMut<Notifications>) {
+    if let Some(reason) = signalling.0.take_refusal() {
+        notifications.warn(reason);
+    }
+}
+
 fn wait_for_players(
     mut commands: Commands,
     socket: Option<ResMut<IrohSocket>>,
+    role: Res<NetworkRole>,
+    source: SetupSource,
+    mut negotiation: ResMut<Negotiation>,
+    mut spectators: ResMut<Spectators>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
     let Some(mut socket) = socket else {
@@ -73,41 +251,141 @@ fn wait_for_players(
 
     // Check for new connections
     socket.update_peers();
-    let players = socket.players();
-
-    let num_players = 2;
-    if players.len() < num_players {
+    let peers = socket.connected_peers().collect::<Vec<_>>();
+    if peers.is_empty() {
         return; // wait for more players
     }
 
+    let negotiated = match_setup::negotiate(&mut socket, &peers, *role, &mut negotiation, &source);
+    if spectators.0 != negotiation.spectators() {
+        spectators.0 = negotiation.spectators().to_vec();
+    }
+    let setup = match negotiated {
+        Ok(Some(setup)) => setup,
+        Ok(None) => return, // still negotiating
+        Err(reason) => {
+            error!("Match setup failed: {reason}");
+            commands.insert_resource(MatchmakingError(reason));
+            return;
+        }
+    };
+
+    let Some(own_id) = socket.id() else {
+        return;
+    };
+    let num_players = setup.players.len();
+    if !setup
+        .players
+        .iter()
+        .all(|player| *player == own_id || peers.contains(player))
+    {
+        return; // wait for the other players to connect to us
+    }
+
+    let input_delay = match source.args.input_delay {
+        InputDelay::Frames(frames) => frames,
+        // spectators have no inputs to delay
+        InputDelay::Auto if matches!(*role, NetworkRole::Spectator) => 0,
+        InputDelay::Auto => {
+            let remote_players = setup
+                .players
+                .iter()
+                .copied()
+                .filter(|player| *player != own_id)
+                .collect::<Vec<_>>();
+            let Some(round_trip) = negotiation.round_trip(&remote_players) else {
+                return; // still measuring the latency
+            };
+            let input_delay = match_setup::auto_input_delay(round_trip);
+            info!(
+                "Round trip time is {round_trip:?}, picking an input delay of {input_delay} frames"
+            );
+            input_delay
+        }
+    };
+
     info!("All peers have joined, going in-game");
 
+    commands.insert_resource(CurrentLevel(source.level_handles.0[&setup.level].clone()));
+    commands.insert_resource(setup.rules);
+    commands.insert_resource(MatchRng::new(setup.seed));
+
     let mut session_builder = ggrs::SessionBuilder::<Config>::new()
         .with_num_players(num_players)
-        .with_input_delay(2);
+        .with_input_delay(input_delay)
+        .with_max_prediction_window(source.args.max_prediction)
+        // keep the match paused this long while a dropped peer reconnects
+        .with_disconnect_timeout(connection::RECONNECT_GRACE);
 
-    for (i, player) in players.into_iter().enumerate() {
-        session_builder = session_builder
-            .add_player(player, i)
-            .expect("failed to add player");
+    if let NetworkRole::Spectator = *role {
+        let Some(host) = negotiation.host() else {
+            return;
+        };
+        info!("Watching the match of {host}");
+        let Ok(channel) = socket.take_channel(0) else {
+            return;
+        };
+        let spectator_session = session_builder.start_spectator_session(host, channel);
+        commands.insert_resource(bevy_ggrs::Session::Spectator(spectator_session));
+        next_state.set(GameState::InGame);
+        return;
+    }
+
+    for (handle, player) in setup.players.iter().enumerate() {
+        let player = if *player == own_id {
+            ggrs::PlayerType::Local
+        } else {
+            ggrs::PlayerType::Remote(*player)
+        };
+        session_builder = match session_builder.add_player(player, handle) {
+            Ok(session_builder) => session_builder,
+            Err(e) => return fail_session(&mut commands, e),
+        };
+    }
+    // spectators get handles after the players
+    for (i, spectator) in spectators.0.iter().enumerate() {
+        session_builder = match session_builder
+            .add_player(ggrs::PlayerType::Spectator(*spectator), num_players + i)
+        {
+            Ok(session_builder) => session_builder,
+            Err(e) => return fail_session(&mut commands, e),
+        };
     }
 
     // move the channel out of the socket (required because GGRS takes ownership of it)
-    let channel = socket.take_channel(0).unwrap();
+    let Ok(channel) = socket.take_channel(0) else {
+        return;
+    };
 
     // start the GGRS session
-    let ggrs_session = session_builder
-        .start_p2p_session(channel)
-        .expect("failed to start session");
+    let ggrs_session = match session_builder.start_p2p_session(channel) {
+        Ok(ggrs_session) => ggrs_session,
+        Err(e) => return fail_session(&mut commands, e),
+    };
 
     commands.insert_resource(bevy_ggrs::Session::P2P(ggrs_session));
 
     next_state.set(GameState::InGame);
 }
 
-fn start_synctest_session(mut commands: Commands, mut next_state: ResMut<NextState<GameState>>) {
+fn fail_session(commands: &mut Commands, e: ggrs::GgrsError) {
+    error!("Failed to start the session: {e}");
+    commands.insert_resource(MatchmakingError(format!("Cannot start the session: {e}")));
+}
+
+fn start_synctest_session(
+    mut commands: Commands,
+    args: Res<Args>,
+    level_handles: Res<LevelHandles>,
+    levels: Res<Assets<Level>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let Some(level) = level_handles.get(&args.level, &levels) else {
+        return; // level is still loading
+    };
+
     info!("Starting synctest session");
-    let num_players = 2;
+    let num_players = level.num_players();
 
     let mut session_builder = ggrs::SessionBuilder::<Config>::new().with_num_players(num_players);
 
@@ -121,6 +399,8 @@ fn start_synctest_session(mut commands: Commands, mut next_state: ResMut<NextSta
         .start_synctest_session()
         .expect("failed to start session");
 
+    commands.insert_resource(CurrentLevel(level_handles.0[&args.level].clone()));
+    commands.insert_resource(MatchRng::new(MatchRng::random_seed()));
     commands.insert_resource(bevy_ggrs::Session::SyncTest(ggrs_session));
 
     next_state.set(GameState::InGame);
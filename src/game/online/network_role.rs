@@ -4,6 +4,8 @@ use bevy::ecs::resource::Resource;
 pub enum NetworkRole {
     Host,
     Client,
+    /// Watches the match of the host without a paddle.
+    Spectator,
 }
 
 impl NetworkRole {
@@ -11,6 +13,7 @@ impl NetworkRole {
         match self {
             NetworkRole::Host => "Host a Game",
             NetworkRole::Client => "Join Game",
+            NetworkRole::Spectator => "Watch Game",
         }
     }
 }
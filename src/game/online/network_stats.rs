@@ -0,0 +1,238 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_ggrs::{LoadWorld, Session, ggrs::P2PSession};
+use matchbox_socket::PeerId;
+
+use super::{IrohSocket, Signalling, p2p_mode};
+use crate::{
+    args::Args,
+    game::{Config, GameState},
+};
+
+pub struct NetworkStatsPlugin;
+
+impl Plugin for NetworkStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Rollbacks>()
+            .add_systems(
+                OnEnter(GameState::InGame),
+                (setup_network_stats_overlay, start_network_stats_log).run_if(p2p_mode),
+            )
+            .add_systems(LoadWorld, count_rollback)
+            .add_systems(
+                Update,
+                (
+                    toggle_network_stats_overlay,
+                    (update_network_stats, log_network_stats)
+                        .chain()
+                        .run_if(resource_exists::<IrohSocket>),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(resource_exists::<Session<Config>>),
+            )
+            .add_systems(OnExit(GameState::InGame), stop_network_stats_log);
+    }
+}
+
+/// Key showing or hiding the overlay.
+const TOGGLE_KEY: KeyCode = KeyCode::F3;
+
+/// Rollbacks counted over the last second.
+#[derive(Resource, Default)]
+struct Rollbacks {
+    /// Rollbacks since `since`.
+    count: u32,
+    since: Duration,
+    /// Rollbacks in the last full second.
+    per_second: u32,
+}
+
+fn count_rollback(mut rollbacks: ResMut<Rollbacks>) {
+    rollbacks.count += 1;
+}
+
+/// When to write the stats to the log next, if at all.
+#[derive(Resource)]
+struct NetworkStatsLog(Timer);
+
+fn start_network_stats_log(mut commands: Commands, args: Res<Args>) {
+    if 0.0 < args.network_stats_interval {
+        commands.insert_resource(NetworkStatsLog(Timer::from_seconds(
+            args.network_stats_interval,
+            TimerMode::Repeating,
+        )));
+    }
+}
+
+fn stop_network_stats_log(mut commands: Commands) {
+    commands.remove_resource::<NetworkStatsLog>();
+}
+
+/// Connection quality to one remote peer.
+struct PeerStats {
+    peer: PeerId,
+    players: String,
+    ping: u128,
+    send_queue_len: usize,
+    kbps_sent: usize,
+    local_frames_behind: i32,
+    remote_frames_behind: i32,
+    path: &'static str,
+}
+
+impl std::fmt::Display for PeerStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: ping {} ms, queue {}, {} kbps, behind {} local / {} remote, {}",
+            self.players,
+            self.ping,
+            self.send_queue_len,
+            self.kbps_sent,
+            self.local_frames_behind,
+            self.remote_frames_behind,
+            self.path
+        )
+    }
+}
+
+/// GGRS stats of every connected peer that has some yet, with the path its iroh connection takes.
+fn peer_stats(
+    session: &P2PSession<Config>,
+    socket: &IrohSocket,
+    signalling: Option<&Signalling>,
+) -> Vec<PeerStats> {
+    let mut peers = socket.connected_peers().collect::<Vec<_>>();
+    peers.sort();
+    peers
+        .into_iter()
+        .filter_map(|peer| {
+            let handles = session.handles_by_address(peer);
+            // stats are only available once the peers synchronized
+            let stats = session.network_stats(*handles.first()?).ok()?;
+            let players = if handles
+                .iter()
+                .any(|handle| session.num_players() <= *handle)
+            {
+                "Spectator".to_string()
+            } else {
+                handles
+                    .iter()
+                    .map(|handle| format!("P{}", handle + 1))
+                    .collect::<Vec<_>>()
+                    .join("+")
+            };
+            Some(PeerStats {
+                peer,
+                players,
+                ping: stats.ping,
+                send_queue_len: stats.send_queue_len,
+                kbps_sent: stats.kbps_sent,
+                local_frames_behind: stats.local_frames_behind,
+                remote_frames_behind: stats.remote_frames_behind,
+                path: signalling.map_or("path unknown", |signalling| signalling.0.path(peer)),
+            })
+        })
+        .collect()
+}
+
+#[derive(Component)]
+struct NetworkStatsOverlay;
+
+fn setup_network_stats_overlay(mut commands: Commands) {
+    commands.spawn((
+        NetworkStatsOverlay,
+        DespawnOnExit(GameState::InGame),
+        Text::default(),
+        TextFont::from_font_size(16.0),
+        TextColor(Color::srgb(0.8, 1.0, 0.8)),
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.0),
+            bottom: Val::Px(10.0),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+        ZIndex(1500),
+        Visibility::Hidden,
+    ));
+}
+
+fn toggle_network_stats_overlay(
+    keys: Res<ButtonInput<KeyCode>>,
+    overlay: Option<Single<&mut Visibility, With<NetworkStatsOverlay>>>,
+) {
+    let Some(mut overlay) = overlay else {
+        return;
+    };
+    if keys.just_pressed(TOGGLE_KEY) {
+        overlay.toggle_inherited_hidden();
+    }
+}
+
+fn update_network_stats(
+    time: Res<Time<Real>>,
+    mut rollbacks: ResMut<Rollbacks>,
+    session: Res<Session<Config>>,
+    socket: Res<IrohSocket>,
+    signalling: Option<Res<Signalling>>,
+    overlay: Option<Single<(&mut Text, &Visibility), With<NetworkStatsOverlay>>>,
+) {
+    let now = time.elapsed();
+    if Duration::from_secs(1) <= now - rollbacks.since {
+        rollbacks.per_second = rollbacks.count;
+        rollbacks.count = 0;
+        rollbacks.since = now;
+    }
+    let Some((mut text, visibility)) = overlay.map(Single::into_inner) else {
+        return;
+    };
+    let Session::P2P(session) = session.as_ref() else {
+        return;
+    };
+    if *visibility == Visibility::Hidden {
+        return;
+    }
+    let mut lines = vec![format!("Rollbacks: {}/s", rollbacks.per_second)];
+    lines.extend(
+        peer_stats(session, &socket, signalling.as_deref())
+            .iter()
+            .map(ToString::to_string),
+    );
+    text.0 = lines.join("\n");
+}
+
+fn log_network_stats(
+    time: Res<Time<Real>>,
+    log: Option<ResMut<NetworkStatsLog>>,
+    rollbacks: Res<Rollbacks>,
+    session: Res<Session<Config>>,
+    socket: Res<IrohSocket>,
+    signalling: Option<Res<Signalling>>,
+) {
+    let Some(mut log) = log else {
+        return;
+    };
+    if !log.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    let Session::P2P(session) = session.as_ref() else {
+        return;
+    };
+    for stats in peer_stats(session, &socket, signalling.as_deref()) {
+        info!(
+            peer = %stats.peer,
+            players = %stats.players,
+            ping = stats.ping as u64,
+            send_queue_len = stats.send_queue_len,
+            kbps_sent = stats.kbps_sent,
+            local_frames_behind = stats.local_frames_behind,
+            remote_frames_behind = stats.remote_frames_behind,
+            rollbacks_per_second = rollbacks.per_second,
+            path = stats.path,
+            "Network stats"
+        );
+    }
+}
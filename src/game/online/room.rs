@@ -0,0 +1,209 @@
+use std::{fmt, net::SocketAddr, str::FromStr};
+
+use data_encoding::BASE32_NOPAD;
+use iroh::{NodeAddr, PublicKey, RelayUrl, SecretKey};
+use iroh_gossip::proto::TopicId;
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever the binary layout of [`RoomTicket`] changes.
+const TICKET_VERSION: u8 = 1;
+
+/// Put in front of a room code to join the room as a spectator.
+pub const SPECTATE_PREFIX: &str = "spectate/";
+
+/// A match room: the host node everybody bootstraps from, plus a nonce the host picks for every new room.
+///
+/// Each room has its own gossip topic, so peers of unrelated matches never meet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomId {
+    pub host: PublicKey,
+    pub nonce: u64,
+}
+
+impl RoomId {
+    pub fn new(host: PublicKey) -> Self {
+        Self {
+            host,
+            nonce: uuid::Uuid::new_v4().as_u64_pair().0,
+        }
+    }
+
+    pub fn topic(&self) -> TopicId {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(b"online-breakout room");
+        hasher.update(self.host.as_bytes());
+        hasher.update(&self.nonce.to_le_bytes());
+        TopicId::from_bytes(*hasher.finalize().as_bytes())
+    }
+}
+
+impl fmt::Display for RoomId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{:016x}", self.host, self.nonce)
+    }
+}
+
+/// Everything a client needs to join a room, encoded as lowercase base32.
+///
+/// A ticket also carries how to reach the host, so joining works without a discovery lookup.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomTicket {
+    pub room: RoomId,
+    pub relay_url: Option<RelayUrl>,
+    pub direct_addresses: Vec<SocketAddr>,
+}
+
+impl RoomTicket {
+    pub fn new(room: RoomId, host: NodeAddr) -> Self {
+        Self {
+            room,
+            relay_url: host.relay_url,
+            direct_addresses: host.direct_addresses.into_iter().collect(),
+        }
+    }
+
+    pub fn has_addresses(&self) -> bool {
+        self.relay_url.is_some() || !self.direct_addresses.is_empty()
+    }
+
+    pub fn host_addr(&self) -> NodeAddr {
+        NodeAddr::from_parts(
+            self.room.host,
+            self.relay_url.clone(),
+            self.direct_addresses.iter().copied(),
+        )
+    }
+}
+
+impl fmt::Display for RoomTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = vec![TICKET_VERSION];
+        bytes.extend(postcard::to_stdvec(self).map_err(|_| fmt::Error)?);
+        f.write_str(&BASE32_NOPAD.encode(&bytes).to_ascii_lowercase())
+    }
+}
+
+/// A hash of the host's node id standing in for a [`RoomTicket`], which the host's beacon hands out.
+///
+/// The code commits to its host, so a ticket for any other host is refused,
+/// whoever answers in place of the beacon. Shown as two groups of five base32 characters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShortCode([u8; 6]);
+
+impl ShortCode {
+    /// The code of every room `host` opens, the same across launches with a stored identity.
+    pub fn of_host(host: &PublicKey) -> Self {
+        let hash = blake3::derive_key("online-breakout short code", host.as_bytes());
+        Self(hash[..6].try_into().unwrap())
+    }
+
+    /// Whether `ticket` is for a room of the host this code stands for.
+    pub fn matches(&self, ticket: &RoomTicket) -> bool {
+        *self == Self::of_host(&ticket.room.host)
+    }
+
+    /// Identity of the beacon serving the ticket for this code.
+    pub fn beacon_key(&self) -> SecretKey {
+        SecretKey::from_bytes(&blake3::derive_key(
+            "online-breakout room code beacon",
+            &self.0,
+        ))
+    }
+}
+
+impl fmt::Display for ShortCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = BASE32_NOPAD.encode(&self.0).to_ascii_lowercase();
+        let (first, second) = code.split_at(code.len() / 2);
+        write!(f, "{first}-{second}")
+    }
+}
+
+/// What a client can join with: a short code or a whole ticket.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Invite {
+    Short(ShortCode),
+    Ticket(RoomTicket),
+}
+
+impl FromStr for Invite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = invite_code(s);
+        let code = code.strip_prefix(SPECTATE_PREFIX).unwrap_or(code);
+        let short = code.replace('-', "").to_ascii_uppercase();
+        if let Ok(bytes) = BASE32_NOPAD.decode(short.as_bytes())
+            && let Ok(bytes) = bytes.try_into()
+        {
+            return Ok(Self::Short(ShortCode(bytes)));
+        }
+        code.parse().map(Self::Ticket)
+    }
+}
+
+/// Whether an invite code or link asks to watch the match instead of playing.
+pub fn is_spectator_invite(invite: &str) -> bool {
+    invite_code(invite).starts_with(SPECTATE_PREFIX)
+}
+
+/// The part of an invite after the `#` of a link.
+fn invite_code(invite: &str) -> &str {
+    invite
+        .rsplit_once('#')
+        .map_or(invite, |(_, code)| code)
+        .trim()
+}
+
+impl FromStr for RoomTicket {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // accept a whole invite link as well as the bare code, for players and spectators alike
+        let code = invite_code(s);
+        let code = code.strip_prefix(SPECTATE_PREFIX).unwrap_or(code);
+        if code.is_empty() {
+            return Err("Room code is empty".into());
+        }
+        let bytes = BASE32_NOPAD
+            .decode(code.to_ascii_uppercase().as_bytes())
+            .map_err(|_| "Room code contains invalid characters or is cut off")?;
+        match bytes.split_first() {
+            Some((&TICKET_VERSION, ticket)) => postcard::from_bytes(ticket)
+                .map_err(|_| "Room code is incomplete or corrupted".into()),
+            Some((version, _)) => Err(format!(
+                "Room code was made by another version of the game (format {version}, expected {TICKET_VERSION})"
+            )),
+            None => Err("Room code is empty".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticket(host: u8) -> RoomTicket {
+        let host = SecretKey::from_bytes(&[host; 32]).public();
+        RoomTicket::new(RoomId::new(host), NodeAddr::new(host))
+    }
+
+    #[test]
+    fn code_matches_ticket_of_its_host() {
+        let ticket = ticket(1);
+        assert!(ShortCode::of_host(&ticket.room.host).matches(&ticket));
+    }
+
+    #[test]
+    fn code_rejects_ticket_of_another_host() {
+        let code = ShortCode::of_host(&ticket(1).room.host);
+        assert!(!code.matches(&ticket(2)));
+    }
+
+    #[test]
+    fn code_round_trips_through_invite() {
+        let code = ShortCode::of_host(&ticket(1).room.host);
+        assert_eq!(code.to_string().len(), 11);
+        assert_eq!(code.to_string().parse(), Ok(Invite::Short(code)));
+    }
+}
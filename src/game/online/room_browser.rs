@@ -0,0 +1,357 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, Weak},
+};
+
+use anyhow::Context;
+use bevy::prelude::*;
+use bevy_wasm_tasks::Tasks;
+use futures::FutureExt;
+use iroh::{Endpoint, PublicKey, SecretKey, protocol::Router};
+use iroh_gossip::{
+    net::{Event, GOSSIP_ALPN, Gossip, GossipEvent, Message},
+    proto::TopicId,
+};
+use n0_future::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use web_time::Instant;
+
+use super::{
+    IrohSocket, Room, Signalling, Spectators,
+    handshake::Handshake,
+    iroh_gossip_signaller::{
+        EndpointOptions, REFRESH_INTERVAL, STALE_CONNECTION_TIMEOUT, with_discovery,
+    },
+    match_setup::SetupSource,
+    network_role::NetworkRole,
+    room::RoomTicket,
+};
+use crate::{
+    args::{Args, SignallingBackend},
+    game::{GameState, rules::MatchRules},
+};
+
+pub struct RoomBrowserPlugin;
+
+impl Plugin for RoomBrowserPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RoomListOpen>()
+            .add_systems(
+                Update,
+                (
+                    start_room_browser
+                        .run_if(room_list_open)
+                        .run_if(not(resource_exists::<RoomBrowser>)),
+                    stop_room_browser
+                        .run_if(not(room_list_open))
+                        .run_if(resource_exists::<RoomBrowser>),
+                )
+                    .run_if(in_state(GameState::Lobby))
+                    .run_if(iroh_signalling),
+            )
+            .add_systems(OnExit(GameState::Lobby), stop_room_browser)
+            .add_systems(
+                Update,
+                (
+                    start_advertising.run_if(not(resource_exists::<RoomAdvert>)),
+                    update_room_advert.run_if(resource_exists::<RoomAdvert>),
+                )
+                    .run_if(in_state(GameState::Matchmaking))
+                    .run_if(resource_exists::<Signalling>),
+            )
+            .add_systems(OnExit(GameState::Matchmaking), stop_advertising);
+    }
+}
+
+/// An open room as its host advertises it on the directory topic.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoomRecord {
+    /// Room code to join with.
+    pub code: String,
+    pub host_name: String,
+    pub level: String,
+    pub rules: MatchRules,
+    /// Where the host is, as the name of its home relay.
+    pub region: String,
+    pub players: usize,
+    pub max_players: usize,
+    /// Rooms of incompatible builds are not listed.
+    pub handshake: Handshake,
+}
+
+/// Well-known topic every host advertises its open room on.
+fn directory_topic() -> TopicId {
+    TopicId::from_bytes(*blake3::hash(b"online-breakout room directory").as_bytes())
+}
+
+/// Directory nodes built in, comma separated, for releases that come with an operator-run node.
+const BUILT_IN_DIRECTORY_PEERS: Option<&str> = option_env!("ONLINE_BREAKOUT_DIRECTORY_PEERS");
+
+/// Length of the host's signature in front of every record on the directory topic.
+const SIGNATURE_LEN: usize = 64;
+
+/// Nodes to meet the others on the directory topic through, those built in and those given with `--directory-peer`.
+fn directory_peers(args: &Args) -> Vec<PublicKey> {
+    BUILT_IN_DIRECTORY_PEERS
+        .into_iter()
+        .flat_map(|peers| peers.split(','))
+        .map(str::trim)
+        .filter(|peer| !peer.is_empty())
+        .chain(args.directory_peers.iter().map(String::as_str))
+        .filter_map(|peer| match peer.parse() {
+            Ok(peer) => Some(peer),
+            Err(e) => {
+                warn!("Ignoring invalid directory peer {peer}: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Broadcasts the latest `record` on the directory topic until its sender is gone.
+///
+/// Records are signed with the key of the room's host, so nobody else can list or alter its room.
+pub async fn advertise(
+    gossip: &Gossip,
+    host_key: &SecretKey,
+    bootstrap: Vec<PublicKey>,
+    mut record: watch::Receiver<RoomRecord>,
+) -> anyhow::Result<()> {
+    let (send, mut recv) = gossip.subscribe(directory_topic(), bootstrap)?.split();
+    let mut refresh_interval = n0_future::time::interval(REFRESH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = refresh_interval.tick().fuse() => {}
+            changed = record.changed().fuse() => {
+                if changed.is_err() {
+                    return Ok(()); // the room closed
+                }
+            }
+            // other hosts' records are of no interest here, but the subscription has to be drained
+            _ = recv.next().fuse() => continue,
+        }
+        let record = serde_json::to_vec(&*record.borrow_and_update())?;
+        let mut message = host_key.sign(&record).to_bytes().to_vec();
+        message.extend(record);
+        send.broadcast(message.into()).await?;
+    }
+}
+
+/// Open rooms heard of on the directory topic, while the lobby lists them.
+#[derive(Resource)]
+pub struct RoomBrowser {
+    rooms: Arc<Mutex<BTreeMap<String, (RoomRecord, Instant)>>>,
+    /// No directory node is known, so there is nobody to hear of rooms from.
+    pub no_directory: bool,
+}
+
+impl RoomBrowser {
+    pub fn rooms(&self) -> Vec<RoomRecord> {
+        let mut rooms = self
+            .rooms
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(record, _)| record.clone())
+            .collect::<Vec<_>>();
+        rooms.sort_by(|a, b| a.host_name.cmp(&b.host_name));
+        rooms
+    }
+}
+
+fn iroh_signalling(args: Res<Args>) -> bool {
+    !args.synctest && args.signalling == SignallingBackend::Iroh
+}
+
+/// Whether the lobby shows the list of open rooms, which is only browsed meanwhile.
+#[derive(Resource, Default)]
+pub struct RoomListOpen(pub bool);
+
+fn room_list_open(list: Res<RoomListOpen>) -> bool {
+    list.0
+}
+
+/// The record in a message from the directory topic, if the host of its room signed it.
+fn verify_record(content: &[u8]) -> anyhow::Result<RoomRecord> {
+    let (signature, bytes) = content
+        .split_at_checked(SIGNATURE_LEN)
+        .context("Room record is cut off")?;
+    let record = serde_json::from_slice::<RoomRecord>(bytes)?;
+    let ticket = record
+        .code
+        .parse::<RoomTicket>()
+        .map_err(anyhow::Error::msg)?;
+    ticket.room.host.verify(bytes, &signature.try_into()?)?;
+    Ok(record)
+}
+
+fn start_room_browser(mut commands: Commands, tasks: Tasks, args: Res<Args>) {
+    let rooms = Arc::default();
+    let browsed = Arc::downgrade(&rooms);
+    let bootstrap = directory_peers(&args);
+    let no_directory = bootstrap.is_empty();
+    commands.insert_resource(RoomBrowser {
+        rooms,
+        no_directory,
+    });
+    if no_directory {
+        return;
+    }
+    let options = match EndpointOptions::from_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            warn!("Cannot browse rooms: {e:#}");
+            return;
+        }
+    };
+    tasks.spawn_auto(async move |_| {
+        if let Err(e) = browse(options, bootstrap, browsed).await {
+            warn!("Room browser stopped: {e:#}");
+        }
+    });
+}
+
+fn stop_room_browser(mut commands: Commands) {
+    // the browsing task notices the list is gone and shuts down
+    commands.remove_resource::<RoomBrowser>();
+}
+
+/// Collects records from the directory topic on an endpoint of its own, dropping them when they get stale.
+async fn browse(
+    options: EndpointOptions,
+    bootstrap: Vec<PublicKey>,
+    rooms: Weak<Mutex<BTreeMap<String, (RoomRecord, Instant)>>>,
+) -> anyhow::Result<()> {
+    let endpoint = with_discovery(
+        Endpoint::builder().alpns(vec![GOSSIP_ALPN.to_vec()]),
+        &options,
+    )?
+    .bind()
+    .await?;
+    let gossip = Gossip::builder().spawn(endpoint.clone()).await?;
+    let router = Router::builder(endpoint)
+        .accept(GOSSIP_ALPN, gossip.clone())
+        .spawn();
+    info!("Browsing rooms through {bootstrap:?}");
+    let (_send, mut recv) = gossip.subscribe(directory_topic(), bootstrap)?.split();
+    let handshake = Handshake::local();
+    let mut refresh_interval = n0_future::time::interval(REFRESH_INTERVAL);
+
+    while let Some(rooms) = rooms.upgrade() {
+        tokio::select! {
+            event = recv.next().fuse() => match event {
+                Some(Ok(Event::Gossip(GossipEvent::Received(Message { content, .. })))) => {
+                    match verify_record(&content) {
+                        Ok(record) if record.handshake == handshake => {
+                            let code = record.code.clone();
+                            rooms.lock().unwrap().insert(code, (record, Instant::now()));
+                        }
+                        Ok(record) => debug!("Ignoring room of an incompatible build: {}", record.code),
+                        Err(e) => debug!("Ignoring invalid room record: {e:#}"),
+                    }
+                }
+                Some(Ok(Event::Lagged)) | Some(Err(_)) | None => {
+                    anyhow::bail!("Room directory subscription closed");
+                }
+                Some(Ok(_)) => {}
+            },
+            _ = refresh_interval.tick().fuse() => {
+                let now = Instant::now();
+                rooms
+                    .lock()
+                    .unwrap()
+                    .retain(|_, (_, seen)| now.duration_since(*seen) < STALE_CONNECTION_TIMEOUT);
+            }
+        }
+    }
+    router.shutdown().await?;
+    Ok(())
+}
+
+/// The record of the room this host advertises, and the task doing so.
+#[derive(Resource)]
+struct RoomAdvert {
+    record: watch::Sender<RoomRecord>,
+    _task: n0_future::task::AbortOnDropHandle<()>,
+}
+
+fn start_advertising(
+    mut commands: Commands,
+    role: Res<NetworkRole>,
+    room: Option<Res<Room>>,
+    signalling: Res<Signalling>,
+    source: SetupSource,
+) {
+    let SetupSource {
+        args,
+        rules,
+        level_handles,
+        levels,
+    } = source;
+    if !args.public || !matches!(*role, NetworkRole::Host) {
+        return;
+    }
+    let (Some(room), Some(level)) = (room, level_handles.get(&args.level, &levels)) else {
+        return;
+    };
+    let region = match room
+        .code
+        .parse::<RoomTicket>()
+        .ok()
+        .and_then(|ticket| ticket.relay_url)
+    {
+        Some(relay) => relay
+            .host_str()
+            .and_then(|host| host.split('.').next())
+            .unwrap_or_default()
+            .to_string(),
+        None if args.lan => "LAN".into(),
+        None => "unknown".into(),
+    };
+    let record = RoomRecord {
+        code: room.code.clone(),
+        host_name: if args.name.is_empty() {
+            "Anonymous".into()
+        } else {
+            args.name.clone()
+        },
+        level: args.level.clone(),
+        rules: rules.clone(),
+        region,
+        players: 1,
+        max_players: level.num_players(),
+        handshake: Handshake::local(),
+    };
+    info!("Advertising room: {record:?}");
+    let (record, receiver) = watch::channel(record);
+    let task = signalling.0.advertise(directory_peers(&args), receiver);
+    commands.insert_resource(RoomAdvert {
+        record,
+        _task: task,
+    });
+}
+
+fn update_room_advert(
+    advert: Res<RoomAdvert>,
+    socket: Option<Res<IrohSocket>>,
+    spectators: Res<Spectators>,
+) {
+    let Some(socket) = socket else {
+        return;
+    };
+    let players = 1 + socket
+        .connected_peers()
+        .count()
+        .saturating_sub(spectators.0.len());
+    advert.record.send_if_modified(|record| {
+        let changed = record.players != players;
+        record.players = players;
+        changed
+    });
+}
+
+fn stop_advertising(mut commands: Commands) {
+    // the match started or was given up, browsers drop the record once it gets stale
+    commands.remove_resource::<RoomAdvert>();
+}
@@ -0,0 +1,95 @@
+//! Short room codes, resolved to the full [`RoomTicket`] through a beacon node the host runs.
+//!
+//! The secret key of the beacon is derived from the code, so a client knows which node to ask
+//! and finds it through discovery like any other node. As that key is no secret, the client
+//! only trusts a ticket for the host the code was derived from.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use bevy::prelude::*;
+use iroh::{
+    Endpoint,
+    endpoint::Connection,
+    protocol::{ProtocolHandler, Router},
+};
+
+use super::{
+    iroh_gossip_signaller::{EndpointOptions, with_discovery},
+    room::{RoomTicket, ShortCode},
+};
+
+pub const ROOM_CODE_ALPN: &[u8] = b"/online-breakout/room-code/1";
+
+/// Tickets are far smaller than this.
+const MAX_TICKET_LEN: usize = 4096;
+
+/// How long a client looks for the beacon of a code before giving up.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Answers every connection with the ticket of the room.
+#[derive(Debug, Clone)]
+struct Beacon {
+    ticket: String,
+}
+
+impl ProtocolHandler for Beacon {
+    fn accept(&self, connection: Connection) -> n0_future::boxed::BoxFuture<anyhow::Result<()>> {
+        let ticket = self.ticket.clone();
+        Box::pin(async move {
+            let mut send = connection.open_uni().await?;
+            send.write_all(ticket.as_bytes()).await?;
+            send.finish()?;
+            // the client closes the connection once it has the ticket
+            connection.closed().await;
+            Ok(())
+        })
+    }
+}
+
+/// Serves `ticket` under `code` for as long as the returned router lives.
+pub async fn serve(
+    code: ShortCode,
+    ticket: &RoomTicket,
+    options: &EndpointOptions,
+) -> anyhow::Result<Router> {
+    let builder = Endpoint::builder()
+        .secret_key(code.beacon_key())
+        .alpns(vec![ROOM_CODE_ALPN.to_vec()]);
+    let endpoint = with_discovery(builder, options)?.bind().await?;
+    info!(
+        "Serving room code {code} from beacon {}",
+        endpoint.node_id()
+    );
+    Ok(Router::builder(endpoint)
+        .accept(
+            ROOM_CODE_ALPN,
+            Beacon {
+                ticket: ticket.to_string(),
+            },
+        )
+        .spawn())
+}
+
+/// Asks the beacon of `code` for the ticket of its room.
+pub async fn resolve(endpoint: &Endpoint, code: ShortCode) -> anyhow::Result<RoomTicket> {
+    let beacon = code.beacon_key().public();
+    info!("Resolving room code {code} through beacon {beacon}");
+    let ticket = n0_future::time::timeout(RESOLVE_TIMEOUT, async {
+        let connection = endpoint.connect(beacon, ROOM_CODE_ALPN).await?;
+        let mut recv = connection.accept_uni().await?;
+        let ticket = recv.read_to_end(MAX_TICKET_LEN).await?;
+        connection.close(0u32.into(), b"done");
+        anyhow::Ok(ticket)
+    })
+    .await
+    .context("No room found for this code, is the host still waiting?")??;
+    let ticket = String::from_utf8(ticket).context("Invalid ticket from the room code")?;
+    let ticket = ticket.parse::<RoomTicket>().map_err(anyhow::Error::msg)?;
+    // the beacon key follows from the code, so anyone knowing the code can answer in its place
+    anyhow::ensure!(
+        code.matches(&ticket),
+        "The room code {code} was answered with a room of another host"
+    );
+    Ok(ticket)
+}
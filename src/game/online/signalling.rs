@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use iroh::protocol::Router;
+use matchbox_socket::{RtcIceServerConfig, WebRtcSocketBuilder};
+
+use super::{
+    Room, Signalling,
+    iroh_gossip_signaller::{EndpointOptions, IrohGossipSignallerBuilder},
+    loopback_signaller::LoopbackSignallerBuilder,
+    network_role::NetworkRole,
+    room::{Invite, RoomId, RoomTicket, SPECTATE_PREFIX, ShortCode},
+    room_code,
+};
+use crate::args::{Args, SignallingBackend};
+
+/// A room opened on the selected signalling backend.
+pub struct OpenedRoom {
+    /// Socket builder signalling through the backend, channels still have to be added.
+    pub builder: WebRtcSocketBuilder,
+    pub room: Room,
+    /// Only the iroh backend can negotiate dropped connections again.
+    pub signalling: Option<Signalling>,
+    /// Hands out the ticket for the short code, as long as it is kept.
+    pub beacon: Option<Router>,
+}
+
+/// Hosts a fresh room, or joins the one the invite in `args` points to.
+pub async fn open_room(args: Args, role: NetworkRole) -> Result<OpenedRoom, String> {
+    match args.signalling {
+        SignallingBackend::Iroh => {
+            // only hosts need a stable identity, which also keeps two windows of one browser apart
+            let persistent = !args.ephemeral && matches!(role, NetworkRole::Host);
+            let options = EndpointOptions::from_args(&args).map_err(|e| format!("{e:#}"))?;
+            let signaller_builder = IrohGossipSignallerBuilder::new(&options, persistent)
+                .await
+                .map_err(|e| format!("Cannot start networking: {e}"))?;
+            // every hosted match gets a fresh room, so it never shares a topic with older ones,
+            // a stored identity only keeps the short code, which leads to the current room
+            let room = RoomId::new(signaller_builder.iroh_id);
+            let (ticket, short_code, beacon) = match role {
+                NetworkRole::Host => {
+                    let ticket = signaller_builder
+                        .node_addr()
+                        .await
+                        .map(|addr| RoomTicket::new(room, addr))
+                        .map_err(|e| format!("Cannot determine our own address: {e}"))?;
+                    let short_code = ShortCode::of_host(&room.host);
+                    let beacon = room_code::serve(short_code, &ticket, &options)
+                        .await
+                        .map_err(|e| format!("Cannot serve the room code: {e:#}"))?;
+                    (ticket, short_code.to_string(), Some(beacon))
+                }
+                NetworkRole::Client | NetworkRole::Spectator => {
+                    let ticket = match args.iroh.parse::<Invite>()? {
+                        Invite::Ticket(ticket) => ticket,
+                        Invite::Short(code) => signaller_builder
+                            .resolve_room_code(code)
+                            .await
+                            .map_err(|e| format!("{e:#}"))?,
+                    };
+                    (ticket, String::new(), None)
+                }
+            };
+            info!("Room ID: {}", ticket.room);
+            info!("Room ticket: {ticket}");
+            Ok(OpenedRoom {
+                builder: WebRtcSocketBuilder::new(ticket.to_string())
+                    .signaller_builder(Arc::new(signaller_builder.clone())),
+                room: Room {
+                    code: ticket.to_string(),
+                    short_code,
+                    web_link: true,
+                },
+                signalling: Some(Signalling(signaller_builder)),
+                beacon,
+            })
+        }
+        SignallingBackend::Matchbox => {
+            let name = room_name(role, &args.iroh)?;
+            let url = format!("{}/{name}", args.signalling_server.trim_end_matches('/'));
+            info!("Room URL: {url}");
+            Ok(OpenedRoom {
+                builder: WebRtcSocketBuilder::new(url),
+                room: Room::named(name),
+                signalling: None,
+                beacon: None,
+            })
+        }
+        SignallingBackend::Loopback => {
+            let name = room_name(role, &args.iroh)?;
+            info!("Loopback room: {name}");
+            Ok(OpenedRoom {
+                builder: WebRtcSocketBuilder::new(name.clone())
+                    .signaller_builder(Arc::new(LoopbackSignallerBuilder)),
+                room: Room::named(name),
+                signalling: None,
+                beacon: None,
+            })
+        }
+    }
+}
+
+/// The STUN and TURN servers given on the command line, or `None` for matchbox's default.
+pub fn ice_server(args: &Args) -> Option<RtcIceServerConfig> {
+    if args.ice_servers.is_empty() {
+        return None;
+    }
+    Some(RtcIceServerConfig {
+        urls: args.ice_servers.clone(),
+        username: args.ice_username.clone(),
+        credential: args.ice_credential.clone(),
+    })
+}
+
+/// Checks an invite before leaving the lobby, with a message for the player if it is no good.
+pub fn check_invite(backend: SignallingBackend, invite: &str) -> Result<(), String> {
+    match backend {
+        SignallingBackend::Iroh => invite.parse::<Invite>().map(|_| ()),
+        SignallingBackend::Matchbox | SignallingBackend::Loopback => {
+            room_name(NetworkRole::Client, invite).map(|_| ())
+        }
+    }
+}
+
+/// Name of a room on a backend without tickets, fresh for the host.
+fn room_name(role: NetworkRole, invite: &str) -> Result<String, String> {
+    if let NetworkRole::Host = role {
+        return Ok(format!(
+            "{:08x}",
+            uuid::Uuid::new_v4().as_u64_pair().0 as u32
+        ));
+    }
+    let name = invite
+        .rsplit_once('#')
+        .map_or(invite, |(_, name)| name)
+        .trim();
+    let name = name.strip_prefix(SPECTATE_PREFIX).unwrap_or(name);
+    if name.is_empty() {
+        return Err("Room name is empty".into());
+    }
+    Ok(name.to_string())
+}
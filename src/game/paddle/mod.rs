@@ -1,63 +1,126 @@
-use bevy::{
-    math::bounding::{Aabb2d, IntersectsVolume},
-    platform::collections::HashMap,
-    prelude::*,
-};
+use bevy::{platform::collections::HashMap, prelude::*};
 use bevy_ggrs::{LocalInputs, LocalPlayers, PlayerInputs, prelude::*};
 
-use super::{Config, components::Team};
-use super::{GameState, field::Wall};
+use super::{
+    Config,
+    components::{Position, Side, Team},
+};
+use super::{
+    GameState,
+    field::{
+        Wall,
+        level::{CurrentLevel, Level},
+    },
+    fixed::{Fixed, FixedAabb, FixedVec2},
+    item::effect::{self, ActiveEffect, EffectKind},
+    round::RoundReset,
+    rules::MatchRules,
+};
 
 pub const PADDLE_WIDTH: f32 = 100.0;
 pub const PADDLE_HEIGHT: f32 = 10.0;
-const PADDLE_Y_POSITION: f32 = 450.0;
 
 const INPUT_LEFT: u8 = 1 << 0;
 const INPUT_RIGHT: u8 = 1 << 1;
+/// Launches balls held by a sticky paddle.
+pub const INPUT_RELEASE: u8 = 1 << 2;
 
 pub struct PaddlePlugin;
 
 impl Plugin for PaddlePlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(PaddleSpeed(300.))
-            .add_systems(OnEnter(GameState::InGame), setup_paddle)
+        app.add_systems(OnEnter(GameState::InGame), setup_paddle)
             .add_systems(ReadInputs, read_local_inputs)
-            .add_systems(GgrsSchedule, move_paddles);
+            .add_systems(GgrsSchedule, move_paddles)
+            .add_systems(GgrsSchedule, reset_paddles.in_set(RoundReset))
+            .rollback_component_with_copy::<Paddle>();
     }
 }
 
-#[derive(Resource)]
-pub struct PaddleSpeed(pub f32);
-
-#[derive(Component)]
+#[derive(Component, Clone, Copy)]
 pub struct Paddle {
-    pub half_size: Vec2,
+    pub half_width: Fixed,
+    pub side: Side,
+}
+
+impl Paddle {
+    fn half_height() -> Fixed {
+        Fixed::from_f32(PADDLE_HEIGHT / 2.0)
+    }
+
+    /// Extent of the paddle in field coordinates.
+    pub fn half_size(&self) -> FixedVec2 {
+        match self.side {
+            Side::Bottom | Side::Top => FixedVec2::new(self.half_width, Self::half_height()),
+            Side::Left | Side::Right => FixedVec2::new(Self::half_height(), self.half_width),
+        }
+    }
+
+    /// Distance of `point` from the paddle `center` along the lane.
+    pub fn offset(&self, center: FixedVec2, point: FixedVec2) -> Fixed {
+        (point - center).dot(self.side.tangent())
+    }
+
+    /// Where a ball of `radius` rests on the paddle face at `offset` from the `center`.
+    pub fn surface(&self, center: FixedVec2, offset: Fixed, radius: Fixed) -> FixedVec2 {
+        center + self.side.tangent() * offset + self.side.normal() * (Self::half_height() + radius)
+    }
+
+    /// Velocity of a ball leaving the paddle at `offset` from its center, steeper towards the ends.
+    pub fn launch_velocity(&self, offset: Fixed, speed: Fixed) -> FixedVec2 {
+        let hit_position = (offset / self.half_width).clamp(-Fixed::ONE, Fixed::ONE);
+        let angle = hit_position * Fixed::PI / Fixed::from_int(3); // Max 60 degrees
+        (self.side.tangent() * angle.sin() + self.side.normal() * angle.cos()) * speed
+    }
 }
 
 fn setup_paddle(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    current_level: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
 ) {
-    let half_size = Vec2::new(PADDLE_WIDTH / 2.0, PADDLE_HEIGHT / 2.0);
-    commands
-        .spawn((
-            Paddle { half_size },
-            Team(0),
-            Mesh2d(meshes.add(Rectangle::new(PADDLE_WIDTH, PADDLE_HEIGHT))),
-            MeshMaterial2d(materials.add(Color::WHITE)),
-            Transform::from_xyz(0.0, -PADDLE_Y_POSITION, 7.0),
-        ))
-        .add_rollback();
-    commands
-        .spawn((
-            Paddle { half_size },
-            Team(1),
-            Mesh2d(meshes.add(Rectangle::new(PADDLE_WIDTH, PADDLE_HEIGHT))),
-            MeshMaterial2d(materials.add(Color::WHITE)),
-            Transform::from_xyz(0.0, PADDLE_Y_POSITION, 7.0),
-        ))
-        .add_rollback();
+    let Some(level) = levels.get(&current_level.0) else {
+        error!("Current level is not loaded, cannot set up paddles.");
+        return;
+    };
+
+    for lane in &level.paddle_lanes {
+        let paddle = Paddle {
+            half_width: Fixed::from_f32(PADDLE_WIDTH / 2.0),
+            side: lane.side,
+        };
+        let half_size = paddle.half_size().to_vec2();
+        commands
+            .spawn((
+                paddle,
+                Team(lane.team),
+                Mesh2d(meshes.add(Rectangle::from_size(2.0 * half_size))),
+                MeshMaterial2d(materials.add(Color::WHITE)),
+                Position(FixedVec2::from_vec2(Vec2::from(lane.position))),
+                Transform::from_translation(Vec2::from(lane.position).extend(7.0)),
+            ))
+            .add_rollback();
+    }
+}
+
+/// Puts every paddle back to its lane center with the default width.
+fn reset_paddles(
+    q_paddles: Query<(&mut Paddle, &Team, &mut Position)>,
+    current_level: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
+) {
+    let Some(level) = levels.get(&current_level.0) else {
+        return;
+    };
+    for (mut paddle, team, mut position) in q_paddles {
+        let Some(lane) = level.lane(*team) else {
+            continue;
+        };
+        paddle.half_width = Fixed::from_f32(PADDLE_WIDTH / 2.0);
+        position.0 = FixedVec2::from_vec2(Vec2::from(lane.position));
+    }
 }
 
 fn read_local_inputs(
@@ -65,10 +128,11 @@ fn read_local_inputs(
     keys: Res<ButtonInput<KeyCode>>,
     touches: Res<Touches>,
     local_players: Res<LocalPlayers>,
-    q_camera: Single<(&Camera, &GlobalTransform)>,
+    camera: Single<&Camera>,
 ) {
     let mut local_inputs = HashMap::new();
-    let (camera, camera_transform) = *q_camera;
+    // inputs are relative to the screen, `move_paddles` turns them into the lane direction
+    let screen_center = camera.logical_viewport_size().unwrap_or_default().x / 2.0;
 
     for handle in &local_players.0 {
         let mut input = 0;
@@ -79,23 +143,22 @@ fn read_local_inputs(
             input |= INPUT_RIGHT;
         }
 
+        if keys.pressed(KeyCode::Space) || keys.pressed(KeyCode::ArrowUp) {
+            input |= INPUT_RELEASE;
+        }
+
+        // a second finger releases the ball
+        if 1 < touches.iter().count() {
+            input |= INPUT_RELEASE;
+        }
         for finger in touches.iter() {
-            let Ok(pos) = camera.viewport_to_world_2d(camera_transform, finger.position()) else {
-                continue;
-            };
-            if pos.x.is_sign_negative() {
+            if finger.position().x < screen_center {
                 input |= INPUT_LEFT;
             } else {
                 input |= INPUT_RIGHT;
             }
         }
 
-        // Reverse input if the role is Host
-        if *handle == 1 {
-            input = ((input & INPUT_LEFT != 0) as u8 * INPUT_RIGHT)
-                | ((input & INPUT_RIGHT != 0) as u8 * INPUT_LEFT);
-        }
-
         local_inputs.insert(*handle, input);
     }
 
@@ -104,49 +167,65 @@ fn read_local_inputs(
 
 pub fn move_paddles(
     time: Res<Time>,
-    paddle_speed: Res<PaddleSpeed>,
+    rules: Res<MatchRules>,
     inputs: Res<PlayerInputs<Config>>,
-    query: Query<(&Paddle, &Team, &mut Transform)>,
-    query_walls: Query<(&Wall, &Transform), Without<Paddle>>,
+    query: Query<(&Paddle, &Team, &mut Position)>,
+    query_walls: Query<(&Wall, &Position), Without<Paddle>>,
+    q_effects: Query<(&ActiveEffect, &Team)>,
 ) {
-    // HACK: `for (paddle, team, mut paddle_transform) in query` does not work for team 1
+    // HACK: `for (paddle, team, mut paddle_position) in query` does not work for team 1
     query
         .into_iter()
-        .for_each(|(paddle, team, mut paddle_transform)| {
+        .for_each(|(paddle, team, mut paddle_position)| {
             let (input, _) = inputs[team.0];
-            let mut direction = 0.0;
+            let mut direction = Fixed::ZERO;
 
             if input & INPUT_LEFT != 0 {
-                direction -= 1.0;
+                direction -= Fixed::ONE;
             }
             if input & INPUT_RIGHT != 0 {
-                direction += 1.0;
+                direction += Fixed::ONE;
             }
 
-            if direction == 0.0 {
+            if direction == Fixed::ZERO {
                 return;
             }
+            if effect::is_active(&q_effects, team, EffectKind::ReversedControls) {
+                direction = -direction;
+            }
 
-            let movement = direction * paddle_speed.0 * time.delta_secs();
-            let mut new_x = paddle_transform.translation.x + movement;
+            let movement = direction
+                * Fixed::from_f32(rules.paddle_speed)
+                * Fixed::from_duration(time.delta());
+            let half_size = paddle.half_size();
+            let mut new_position = paddle_position.0 + paddle.side.tangent() * movement;
 
             // Check wall collision
-            for (wall, wall_transform) in query_walls.iter() {
-                let paddle_aabb = Aabb2d::new(
-                    paddle_transform.translation.truncate().with_x(new_x),
-                    paddle.half_size,
-                );
-                let wall_aabb = Aabb2d::new(wall_transform.translation.truncate(), wall.half_size);
-
-                if paddle_aabb.intersects(&wall_aabb) {
-                    new_x = if paddle_transform.translation.x < wall_transform.translation.x {
-                        wall_aabb.min.x - paddle.half_size.x
-                    } else {
-                        wall_aabb.max.x + paddle.half_size.x
-                    };
+            for (wall, wall_position) in query_walls.iter() {
+                let paddle_aabb = FixedAabb::new(new_position, half_size);
+                let wall_aabb = FixedAabb::new(wall_position.0, wall.half_size);
+
+                if !paddle_aabb.intersects(&wall_aabb) {
+                    continue;
+                }
+                match paddle.side {
+                    Side::Bottom | Side::Top => {
+                        new_position.x = if paddle_position.x < wall_position.x {
+                            wall_aabb.min.x - half_size.x
+                        } else {
+                            wall_aabb.max.x + half_size.x
+                        };
+                    }
+                    Side::Left | Side::Right => {
+                        new_position.y = if paddle_position.y < wall_position.y {
+                            wall_aabb.min.y - half_size.y
+                        } else {
+                            wall_aabb.max.y + half_size.y
+                        };
+                    }
                 }
             }
 
-            paddle_transform.translation.x = new_x;
+            paddle_position.0 = new_position;
         });
 }
@@ -0,0 +1,35 @@
+use std::ops::Range;
+
+use bevy::prelude::*;
+
+/// Deterministic PRNG for simulation decisions.
+///
+/// Both peers start from the seed agreed in the match setup, and it is rolled back with the session.
+#[derive(Resource, Clone, Debug, Hash)]
+pub struct MatchRng {
+    state: u64,
+}
+
+impl MatchRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Returns a fresh seed for a new match.
+    pub fn random_seed() -> u64 {
+        uuid::Uuid::new_v4().as_u64_pair().0
+    }
+
+    /// SplitMix64
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn range(&mut self, range: Range<usize>) -> usize {
+        range.start + (self.next_u64() % (range.end - range.start) as u64) as usize
+    }
+}
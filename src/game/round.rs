@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+use bevy_ggrs::prelude::*;
+
+use super::{GameState, rules::MatchRules, timer::check_victory_conditions};
+
+pub struct RoundPlugin;
+
+impl Plugin for RoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::InGame), (start_match, setup_round_hud))
+            .configure_sets(
+                GgrsSchedule,
+                RoundReset
+                    .after(check_victory_conditions)
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(reset_pending),
+            )
+            .add_systems(
+                GgrsSchedule,
+                finish_reset
+                    .after(RoundReset)
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(reset_pending),
+            )
+            .add_systems(
+                Update,
+                update_round_hud
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(resource_exists::<MatchProgress>),
+            )
+            .rollback_resource_with_clone::<MatchProgress>();
+    }
+}
+
+/// Systems putting the field back to its initial state between rounds, while the GGRS session keeps running.
+#[derive(SystemSet, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct RoundReset;
+
+/// Outcome of a single round.
+#[derive(Clone, Debug)]
+pub struct RoundResult {
+    /// Winning alliance as given by [`MatchRules::alliance`], or `None` for a draw.
+    pub winner: Option<usize>,
+    /// Blocks owned by each player.
+    pub blocks: Vec<usize>,
+    /// Blocks owned by each alliance.
+    pub scores: Vec<usize>,
+    /// Cells captured by each alliance.
+    pub captures: Vec<usize>,
+    /// Whether the round was decided in sudden death.
+    pub overtime: bool,
+}
+
+/// Rounds played so far in a best-of-N match.
+#[derive(Resource, Clone, Default)]
+pub struct MatchProgress {
+    pub results: Vec<RoundResult>,
+    /// Set when a round ended and the field has to be reset before the next one.
+    pub reset_pending: bool,
+}
+
+impl MatchProgress {
+    pub fn wins(&self, alliance: usize) -> u32 {
+        self.results
+            .iter()
+            .filter(|result| result.winner == Some(alliance))
+            .count() as u32
+    }
+
+    /// Winner of the match once it is decided, `Some(None)` for a drawn match.
+    pub fn match_winner(&self, rules: &MatchRules, alliances: usize) -> Option<Option<usize>> {
+        if let Some(alliance) =
+            (0..alliances).find(|alliance| rules.rounds_to_win() <= self.wins(*alliance))
+        {
+            return Some(Some(alliance));
+        }
+        if (self.results.len() as u32) < rules.rounds {
+            return None;
+        }
+        // all rounds played without a majority, e.g. after drawn rounds
+        let best = (0..alliances)
+            .map(|alliance| self.wins(alliance))
+            .max()
+            .unwrap_or_default();
+        let mut leaders = (0..alliances).filter(|alliance| self.wins(*alliance) == best);
+        Some(match (leaders.next(), leaders.next()) {
+            (Some(alliance), None) => Some(alliance),
+            _ => None,
+        })
+    }
+}
+
+fn reset_pending(progress: Option<Res<MatchProgress>>) -> bool {
+    progress.is_some_and(|progress| progress.reset_pending)
+}
+
+fn start_match(mut commands: Commands) {
+    commands.insert_resource(MatchProgress::default());
+}
+
+fn finish_reset(mut progress: ResMut<MatchProgress>) {
+    info!("Starting round {}", progress.results.len() + 1);
+    progress.reset_pending = false;
+}
+
+#[derive(Component)]
+struct RoundHud;
+
+fn setup_round_hud(mut commands: Commands, rules: Res<MatchRules>) {
+    if rules.rounds <= 1 {
+        return;
+    }
+    commands.spawn((
+        RoundHud,
+        DespawnOnExit(GameState::InGame),
+        Text::default(),
+        TextFont::from_font_size(24.0),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            width: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        TextLayout::new_with_justify(Justify::Center),
+        ZIndex(1000),
+    ));
+}
+
+fn update_round_hud(
+    progress: Res<MatchProgress>,
+    rules: Res<MatchRules>,
+    hud: Option<Single<&mut Text, With<RoundHud>>>,
+) {
+    let Some(mut hud) = hud else {
+        return;
+    };
+    let alliances = progress
+        .results
+        .first()
+        .map_or(0, |result| result.scores.len());
+    let wins = (0..alliances)
+        .map(|alliance| progress.wins(alliance).to_string())
+        .collect::<Vec<_>>()
+        .join(" - ");
+    let round = (progress.results.len() + 1).min(rules.rounds as usize);
+    hud.0 = if wins.is_empty() {
+        format!("Round {round}/{}", rules.rounds)
+    } else {
+        format!("Round {round}/{}   {wins}", rules.rounds)
+    };
+}
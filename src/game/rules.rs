@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::components::Team;
+
+/// Match parameters chosen by the host and shared with the client before the session starts.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MatchRules {
+    pub game_duration_secs: f32,
+    pub first_ball_speed: f32,
+    pub paddle_speed: f32,
+    pub multi_ball_count: u32,
+    pub speed_up_multiplier: f32,
+    pub effect_duration_secs: f32,
+    pub team_mode: TeamMode,
+    /// Keep playing after a draw until the next capture breaks the tie.
+    pub sudden_death: bool,
+    pub victory: VictoryCondition,
+    /// Share of all cells an alliance needs for [`VictoryCondition::CellShare`].
+    pub cell_share_percent: u32,
+    /// Number of rounds in a best-of-N match.
+    pub rounds: u32,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self {
+            game_duration_secs: 120., // 2 minutes
+            first_ball_speed: 300.,
+            paddle_speed: 300.,
+            multi_ball_count: 2,
+            speed_up_multiplier: 1.2,
+            effect_duration_secs: 10.,
+            team_mode: TeamMode::FreeForAll,
+            sudden_death: false,
+            victory: VictoryCondition::MostBlocks,
+            cell_share_percent: 60,
+            rounds: 1,
+        }
+    }
+}
+
+/// How players are grouped for scoring.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TeamMode {
+    FreeForAll,
+    /// Even and odd players play together.
+    TwoVsTwo,
+}
+
+impl TeamMode {
+    pub const ALL: [Self; 2] = [Self::FreeForAll, Self::TwoVsTwo];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TeamMode::FreeForAll => "Free for all",
+            TeamMode::TwoVsTwo => "2 vs 2",
+        }
+    }
+}
+
+/// How a round is won. Without an earlier winner, the round is decided when the time is up.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VictoryCondition {
+    /// Most blocks when the time is up.
+    MostBlocks,
+    /// First to own `cell_share_percent` of the cells, otherwise most blocks.
+    CellShare,
+    /// First to own every remaining player cell, otherwise most blocks.
+    Elimination,
+    /// Most captured cells when the time is up.
+    MostCaptures,
+}
+
+impl VictoryCondition {
+    pub const ALL: [Self; 4] = [
+        Self::MostBlocks,
+        Self::CellShare,
+        Self::Elimination,
+        Self::MostCaptures,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            VictoryCondition::MostBlocks => "Most blocks",
+            VictoryCondition::CellShare => "Cell share",
+            VictoryCondition::Elimination => "Elimination",
+            VictoryCondition::MostCaptures => "Most captures",
+        }
+    }
+}
+
+impl MatchRules {
+    /// Round wins needed to take the match.
+    pub fn rounds_to_win(&self) -> u32 {
+        self.rounds / 2 + 1
+    }
+
+    /// Index of the side `team` scores for.
+    pub fn alliance(&self, team: Team) -> usize {
+        match self.team_mode {
+            TeamMode::FreeForAll => team.0,
+            TeamMode::TwoVsTwo => team.0 % 2,
+        }
+    }
+
+    pub fn alliance_count(&self, num_players: usize) -> usize {
+        match self.team_mode {
+            TeamMode::FreeForAll => num_players,
+            TeamMode::TwoVsTwo => num_players.min(2),
+        }
+    }
+
+    /// Whether `a` and `b` play on the same side. Item cells are nobody's ally.
+    pub fn allied(&self, a: Team, b: Team) -> bool {
+        a == b || (a != Team::ITEM && b != Team::ITEM && self.alliance(a) == self.alliance(b))
+    }
+}
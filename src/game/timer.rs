@@ -1,11 +1,14 @@
-use std::cmp::Ordering;
-
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_ggrs::prelude::*;
 
-use super::{GameState, components::Team, field::Cell};
-
-pub const GAME_DURATION_SECS: f32 = 120.; // 2 minutes
+use super::{
+    GameState,
+    components::Team,
+    field::{CaptureCount, Cell},
+    paddle::Paddle,
+    round::{MatchProgress, RoundReset, RoundResult},
+    rules::{MatchRules, VictoryCondition},
+};
 
 pub struct TimerPlugin;
 
@@ -22,68 +25,163 @@ impl Plugin for TimerPlugin {
                 .run_if(resource_exists::<GameTimer>)
                 .after(super::field::toggle_cell),
         )
+        .add_systems(GgrsSchedule, reset_timer.in_set(RoundReset))
         .add_systems(
             Update,
             update_timer_ui
                 .run_if(in_state(GameState::InGame))
                 .run_if(resource_exists::<GameTimer>),
         )
-        .rollback_resource_with_clone::<GameTimer>();
+        .rollback_resource_with_clone::<GameTimer>()
+        .rollback_resource_with_copy::<Overtime>();
     }
 }
 
 #[derive(Resource, Clone)]
 pub struct GameTimer(pub Timer);
 
+/// Set once the regular time ended in a draw and the match went into sudden death.
+#[derive(Resource, Clone, Copy, Default)]
+pub struct Overtime(pub bool);
+
 #[derive(Resource)]
 pub struct GameResult {
-    pub winner: Option<Team>,
-    pub team0_blocks: usize,
-    pub team1_blocks: usize,
+    /// Winning alliance as given by [`MatchRules::alliance`], or `None` for a draw.
+    pub winner: Option<usize>,
+    /// Results of every round played, in order.
+    pub rounds: Vec<RoundResult>,
+    /// Players who left the match and did not reconnect in time.
+    pub forfeited: Vec<usize>,
 }
 
-fn start_game_timer(mut commands: Commands) {
+fn start_game_timer(mut commands: Commands, rules: Res<MatchRules>) {
     commands.insert_resource(GameTimer(Timer::from_seconds(
-        GAME_DURATION_SECS,
+        rules.game_duration_secs,
         TimerMode::Once,
     )));
+    commands.insert_resource(Overtime::default());
+}
+
+fn reset_timer(mut timer: ResMut<GameTimer>, mut overtime: ResMut<Overtime>) {
+    timer.0.reset();
+    overtime.0 = false;
+}
+
+/// Round time and whether it has run into sudden death.
+#[derive(SystemParam)]
+pub struct RoundClock<'w> {
+    timer: ResMut<'w, GameTimer>,
+    overtime: ResMut<'w, Overtime>,
+    time: Res<'w, Time>,
+}
+
+/// What each player holds on the field.
+#[derive(SystemParam)]
+pub struct FieldStanding<'w, 's> {
+    cells: Query<'w, 's, &'static Team, With<Cell>>,
+    paddles: Query<'w, 's, (), With<Paddle>>,
+    captures: Res<'w, CaptureCount>,
 }
 
-fn check_victory_conditions(
+/// Ends the round once it is decided, and the match once enough rounds are won.
+///
+/// On a draw with sudden death enabled the round goes on until a capture breaks the tie.
+pub fn check_victory_conditions(
     mut commands: Commands,
-    mut timer: ResMut<GameTimer>,
-    time: Res<Time>,
-    q_cells: Query<&Team, With<Cell>>,
+    mut clock: RoundClock,
+    mut progress: ResMut<MatchProgress>,
+    field: FieldStanding,
+    rules: Res<MatchRules>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
-    timer.0.tick(time.delta());
-
-    if timer.0.is_finished() {
-        // Count blocks for each team
-        let mut team0_blocks = 0;
-        let mut team1_blocks = 0;
-
-        for team in q_cells.iter() {
-            match team.0 {
-                0 => team0_blocks += 1,
-                1 => team1_blocks += 1,
-                _ => {} // Items don't count
+    clock.timer.0.tick(clock.time.delta());
+
+    // Count blocks for each player, there is one paddle per player
+    let num_players = field.paddles.iter().count();
+    let mut blocks = vec![0; num_players];
+    for team in field.cells.iter() {
+        if let Some(count) = blocks.get_mut(team.0) {
+            *count += 1; // Items don't count
+        }
+    }
+
+    let alliances = rules.alliance_count(num_players);
+    let mut scores = vec![0; alliances];
+    for (player, count) in blocks.iter().enumerate() {
+        scores[rules.alliance(Team(player))] += count;
+    }
+    let mut alliance_captures = vec![0; alliances];
+    for (player, count) in field.captures.0.iter().enumerate().take(num_players) {
+        alliance_captures[rules.alliance(Team(player))] += count;
+    }
+
+    let winner = match early_winner(&rules, &scores, field.cells.iter().count()) {
+        Some(alliance) => Some(alliance),
+        None if !clock.timer.0.is_finished() => return,
+        None => {
+            let measure = match rules.victory {
+                VictoryCondition::MostCaptures => &alliance_captures,
+                _ => &scores,
+            };
+            let winner = leader(measure);
+            if winner.is_none() && rules.sudden_death {
+                if !clock.overtime.0 {
+                    info!("Draw at the end of regular time, going into sudden death");
+                    clock.overtime.0 = true;
+                }
+                return;
             }
+            winner
         }
+    };
+
+    progress.results.push(RoundResult {
+        winner,
+        blocks,
+        scores,
+        captures: alliance_captures,
+        overtime: clock.overtime.0,
+    });
+    info!("Round {} ended, winner: {winner:?}", progress.results.len());
+
+    let Some(match_winner) = progress.match_winner(&rules, alliances) else {
+        progress.reset_pending = true;
+        return;
+    };
 
-        let winner = match team0_blocks.cmp(&team1_blocks) {
-            Ordering::Greater => Some(Team(0)),
-            Ordering::Less => Some(Team(1)),
-            Ordering::Equal => None, // Draw
-        };
+    commands.insert_resource(GameResult {
+        winner: match_winner,
+        rounds: progress.results.clone(),
+        forfeited: Vec::new(),
+    });
 
-        commands.insert_resource(GameResult {
-            winner,
-            team0_blocks,
-            team1_blocks,
-        });
+    next_state.set(GameState::GameOver);
+}
+
+/// Winner before the time is up, for the conditions that allow one.
+fn early_winner(rules: &MatchRules, scores: &[usize], total_cells: usize) -> Option<usize> {
+    match rules.victory {
+        VictoryCondition::CellShare => scores
+            .iter()
+            .position(|score| total_cells * rules.cell_share_percent as usize <= score * 100),
+        VictoryCondition::Elimination => {
+            let mut remaining = (0..scores.len()).filter(|alliance| 0 < scores[*alliance]);
+            match (remaining.next(), remaining.next()) {
+                (Some(alliance), None) if 1 < scores.len() => Some(alliance),
+                _ => None,
+            }
+        }
+        VictoryCondition::MostBlocks | VictoryCondition::MostCaptures => None,
+    }
+}
 
-        next_state.set(GameState::GameOver);
+/// Index of the single highest value, or `None` on a tie.
+fn leader(values: &[usize]) -> Option<usize> {
+    let best = values.iter().copied().max().unwrap_or_default();
+    let mut leaders = (0..values.len()).filter(|index| values[*index] == best);
+    match (leaders.next(), leaders.next()) {
+        (Some(index), None) => Some(index),
+        _ => None, // Draw
     }
 }
 
@@ -93,6 +191,9 @@ struct TimerBarContainer;
 #[derive(Component)]
 struct TimerBar;
 
+#[derive(Component)]
+struct OvertimeLabel;
+
 fn setup_timer_ui(mut commands: Commands) {
     // Simple timer bar at the bottom of the screen
     commands.spawn((
@@ -111,24 +212,53 @@ fn setup_timer_ui(mut commands: Commands) {
         BackgroundColor(Color::srgb(0.3, 0.3, 0.3)),
         BorderColor::all(Color::WHITE),
         ZIndex(1000),
-        children![(
-            TimerBar,
-            DespawnOnExit(GameState::InGame),
-            Node {
-                width: Val::Percent(100.0),
-                height: Val::Percent(100.0),
-                ..default()
-            },
-            BackgroundColor(Color::srgb(0.2, 0.8, 0.2)),
-        )],
+        children![
+            (
+                TimerBar,
+                DespawnOnExit(GameState::InGame),
+                Node {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    ..default()
+                },
+                BackgroundColor(Color::srgb(0.2, 0.8, 0.2)),
+            ),
+            (
+                OvertimeLabel,
+                DespawnOnExit(GameState::InGame),
+                Text::new("SUDDEN DEATH"),
+                TextFont::from_font_size(18.0),
+                TextColor(Color::srgb(0.9, 0.3, 0.9)),
+                Node {
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(24.0), // just above the bar
+                    ..default()
+                },
+                Visibility::Hidden,
+            ),
+        ],
     ));
 }
 
 fn update_timer_ui(
     timer: Res<GameTimer>,
+    overtime: Res<Overtime>,
+    time: Res<Time>,
     timer_bar: Single<(&mut Node, &mut BackgroundColor), With<TimerBar>>,
+    mut overtime_label: Single<&mut Visibility, With<OvertimeLabel>>,
 ) {
     let (mut node, mut background_color) = timer_bar.into_inner();
+
+    if overtime.0 {
+        // Sudden death has no time limit, so show a full pulsing bar instead
+        let pulse = (time.elapsed_secs() * 4.0).sin() * 0.5 + 0.5;
+        node.width = Val::Percent(100.0);
+        background_color.0 = Color::srgb(0.6 + 0.3 * pulse, 0.1, 0.6 + 0.3 * pulse); // Magenta
+        overtime_label.set_if_neq(Visibility::Inherited);
+        return;
+    }
+    overtime_label.set_if_neq(Visibility::Hidden);
+
     let progress = timer.0.fraction();
     let remaining_progress = 1.0 - progress;
 
@@ -1,8 +1,6 @@
 use bevy::prelude::*;
 use clap::Parser;
-
-mod args;
-mod game;
+use online_breakout::{args, game};
 
 fn main() {
     let args = get_args();
@@ -42,6 +40,28 @@ fn get_args() -> args::Args {
             args.iroh = hash.trim_start_matches('#').to_string();
         }
 
+        // relays and ICE servers come from the query, e.g. `?relay-url=https://relay.example.com`
+        if let Ok(search) = location.search()
+            && let Ok(params) = web_sys::UrlSearchParams::new_with_str(&search)
+        {
+            let list =
+                |value: String| -> Vec<String> { value.split(',').map(str::to_string).collect() };
+            if let Some(relay) = params.get("relay") {
+                match <args::RelayChoice as clap::ValueEnum>::from_str(&relay, true) {
+                    Ok(relay) => args.relay = relay,
+                    Err(e) => warn!("Ignoring relay parameter: {e}"),
+                }
+            }
+            if let Some(urls) = params.get("relay-url") {
+                args.relay_urls = list(urls);
+            }
+            if let Some(servers) = params.get("ice-server") {
+                args.ice_servers = list(servers);
+            }
+            args.ice_username = params.get("ice-username").or(args.ice_username);
+            args.ice_credential = params.get("ice-credential").or(args.ice_credential);
+        }
+
         args
     }
 }
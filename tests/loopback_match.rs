@@ -0,0 +1,153 @@
+//! Two apps in one process matched through the loopback signalling backend.
+
+use std::time::{Duration, Instant};
+
+use bevy::{
+    prelude::*,
+    render::{RenderPlugin, settings::WgpuSettings},
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+use bevy_ggrs::RollbackFrameCount;
+use clap::Parser;
+use matchbox_socket::PeerId;
+use online_breakout::{
+    args::Args,
+    game::{
+        GamePlugin, GameState, IrohSocket, NetworkRole, RECONNECT_GRACE, Room, loopback_signaller,
+    },
+};
+
+/// The peers have to connect over WebRTC and measure their latency, which takes a few seconds.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The whole game without a window or a GPU.
+fn headless_app(args: &[&str]) -> App {
+    let args = Args::parse_from(
+        ["online-breakout", "--signalling", "loopback"]
+            .iter()
+            .chain(args),
+    );
+    let mut app = App::new();
+    app.add_plugins((
+        DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                ..default()
+            })
+            .set(RenderPlugin {
+                render_creation: WgpuSettings {
+                    backends: None,
+                    ..default()
+                }
+                .into(),
+                ..default()
+            })
+            .disable::<WinitPlugin>(),
+        GamePlugin,
+    ))
+    .insert_resource(args);
+    app.finish();
+    app.cleanup();
+    app
+}
+
+fn start_matchmaking(app: &mut App, role: NetworkRole) {
+    app.insert_resource(role);
+    app.world_mut()
+        .resource_mut::<NextState<GameState>>()
+        .set(GameState::Matchmaking);
+}
+
+fn in_game(app: &App) -> bool {
+    *app.world().resource::<State<GameState>>().get() == GameState::InGame
+}
+
+/// Runs a host and a client until both are in the match.
+fn start_match() -> (App, App) {
+    let mut host = headless_app(&["--level", "classic"]);
+    start_matchmaking(&mut host, NetworkRole::Host);
+    let mut client = None;
+
+    let deadline = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        host.update();
+        // the client joins once the host's room has a name
+        if client.is_none()
+            && let Some(room) = host.world().get_resource::<Room>()
+        {
+            let mut app = headless_app(&["--iroh", room.code.as_str()]);
+            start_matchmaking(&mut app, NetworkRole::Client);
+            client = Some(app);
+        }
+        if let Some(app) = &mut client {
+            app.update();
+            if in_game(&host) && in_game(app) {
+                return (host, client.unwrap());
+            }
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    panic!("the apps did not reach the match within {TIMEOUT:?}");
+}
+
+fn peer_id(app: &mut App) -> PeerId {
+    app.world_mut()
+        .resource_mut::<IrohSocket>()
+        .id()
+        .expect("the socket has an id once in the match")
+}
+
+fn connected_to(app: &App, peer: PeerId) -> bool {
+    app.world()
+        .resource::<IrohSocket>()
+        .connected_peers()
+        .any(|connected| connected == peer)
+}
+
+fn frame(app: &App) -> i32 {
+    app.world().resource::<RollbackFrameCount>().0
+}
+
+#[test]
+fn host_and_client_reach_the_match() {
+    start_match();
+}
+
+#[test]
+fn match_resumes_after_a_dropped_connection() {
+    let (mut host, mut client) = start_match();
+    let host_id = peer_id(&mut host);
+    let client_id = peer_id(&mut client);
+    let room = host.world().resource::<Room>().code.clone();
+    loopback_signaller::interrupt(&room, client_id);
+
+    let deadline = Instant::now() + RECONNECT_GRACE;
+    let mut dropped = false;
+    let mut resumed_at = None;
+    while Instant::now() < deadline {
+        host.update();
+        client.update();
+        assert!(
+            in_game(&host) && in_game(&client),
+            "the match ended after the drop"
+        );
+        if !dropped {
+            dropped = !connected_to(&client, host_id);
+        } else if resumed_at.is_none()
+            && connected_to(&client, host_id)
+            && connected_to(&host, client_id)
+        {
+            resumed_at = Some(frame(&host));
+        }
+        // the renegotiated connection carries the inputs, so the simulation goes on
+        if resumed_at.is_some_and(|resumed| resumed + 60 < frame(&host).min(frame(&client))) {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    panic!(
+        "the match did not resume within {RECONNECT_GRACE:?} (dropped: {dropped}, resumed at frame: {resumed_at:?})"
+    );
+}